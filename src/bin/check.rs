@@ -1,61 +1,66 @@
-use tokio;
-use serde::{Deserialize, Serialize};
-
-use std::collections::HashMap;
-
+use sneakattackproto::data::{self, ALIASES_FILE, RALLY_DATA, UIDS_FILE};
 use sneakattackproto::spreadsheet;
 use sneakattackproto::structures::{self, UidMap};
-use std::sync::OnceLock;
-use regex::Regex;
+use sneakattackproto::validate::{self, Problem, ProblemKind};
 
-// It'd be better if this was Cow or whatever
-#[derive(Clone)]
-struct RallyState {
-    uids: HashMap<usize, structures::Uid>,
-    rallies: HashMap<usize, HashMap<String, structures::Rally>>,
+/// Build a sheet for every entrant against their neighbours in class
+fn check_spreadsheets(rally: &structures::Rally, uids: &UidMap) -> Vec<Problem> {
+    let mut problems = vec![];
+    for entry in &rally.entries {
+        let class: Vec<_> = rally.entries.iter()
+            .filter(|x| x.class == entry.class)
+            .map(|x| x.number)
+            .collect();
+        let position = class.iter().position(|x| *x == entry.number).unwrap();
+        let benchmarks: Vec<_> = [position.checked_sub(1), Some(position + 1)].into_iter()
+            .flatten()
+            .filter_map(|i| class.get(i).copied())
+            .collect();
+        if let Err(e) = spreadsheet::build_spreadsheet(rally, uids, entry.number, &benchmarks, spreadsheet::WorkbookOptions::default()) {
+            problems.push(Problem::new(rally, Some(entry.number), ProblemKind::Spreadsheet { message: e.to_string() }));
+        }
+    }
+    problems
 }
 
-// Keep these in the order you want them displayed in the web interface
-const RALLY_DATA: &[(usize, &'static str)] = &[
-    (2026, "2026rallies.json"),
-    (2025, "2025rallies.json"),
-    (2024, "2024rallies.json"),
-];
-
-fn build_state() -> RallyState {
+fn main() {
+    let loaded = match data::load_aliases(ALIASES_FILE).and_then(|aliases| data::load(UIDS_FILE, RALLY_DATA, &aliases)) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("error: {}", e);
+            std::process::exit(1);
+        },
+    };
 
-    let mut uids = UidMap::new();
-    let uids_list: Vec<structures::Uid> = spreadsheet::load_sneakattack_json("uidsSmall.json").unwrap();
-    for uid in uids_list {
-        uids.insert(uid.uid, uid);
+    let mut errors = 0;
+    let mut warnings = 0;
+    for (year, e) in &loaded.errors {
+        errors += 1;
+        println!("{} error: {}", year, e);
     }
-
-    let mut rallies = HashMap::new();
-    for (year, data_file) in RALLY_DATA {
-        let mut data = HashMap::new();
-        let rallies_list: Vec<structures::Rally> = spreadsheet::load_sneakattack_json(data_file).unwrap();
-        for rally in rallies_list {
-            data.insert(rally.slug.clone(), rally);
+    for (year, rallies) in &loaded.rallies {
+        let mut rallies: Vec<_> = rallies.iter().collect();
+        rallies.sort_by_key(|rally| &rally.slug);
+        for rally in rallies {
+            let mut problems = validate::validate_rally(rally, &loaded.uids);
+            // Spreadsheet problems in a structurally broken rally would only repeat these
+            if problems.iter().all(|p| p.is_warning()) {
+                problems.extend(check_spreadsheets(rally, &loaded.uids));
+            }
+            for problem in problems {
+                if problem.is_warning() {
+                    warnings += 1;
+                    println!("{} warning: {}", year, problem);
+                } else {
+                    errors += 1;
+                    println!("{} error: {}", year, problem);
+                }
+            }
         }
-        rallies.insert(*year, data);
     }
 
-    RallyState {
-        uids,
-        rallies,
+    println!("{} errors, {} warnings", errors, warnings);
+    if errors > 0 {
+        std::process::exit(1);
     }
 }
-
-fn main() {
-    let state = build_state();
-
-    println!("state builds")
-}
-
-#[derive(Deserialize, Debug)]
-#[allow(dead_code)]
-struct TimeComp {
-    driver: usize,
-    benchmarks: Vec<usize>,
-    event: String,
-}
@@ -1,26 +1,378 @@
-use sneakattackproto::spreadsheet;
-use sneakattackproto::structures::{self, UidMap};
+use clap::{Parser, Subcommand};
+use regex::Regex;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use sneakattackproto::comparison::{Comparison, FieldScope};
+use sneakattackproto::data;
+use sneakattackproto::error::DataError;
+use sneakattackproto::history::History;
+use sneakattackproto::points::{Championship, PointsScheme};
+use sneakattackproto::season::Season;
+use sneakattackproto::spreadsheet::{self, WorkbookOptions};
+use sneakattackproto::structures::{self, Precision, UidMap};
+use sneakattackproto::suggest;
 
-fn main() -> Result<(), reqwest::Error> {
-    // TODO(richo) Argparsing eventually
-    let main_driver = 107;
-    let benchmark_drivers = [1, 423, 25, 135, 965, 210];
+#[derive(Parser, Debug)]
+#[command(about = "Build a stage time comparison spreadsheet", subcommand_negates_reqs = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    let mut uids = UidMap::new();
-    let uids_list: Vec<structures::Uid> = spreadsheet::load_sneakattack_json("uidsSmall.json").unwrap();
-    for uid in uids_list {
-        uids.insert(uid.uid, uid);
+    /// Season to look the rally up in
+    #[arg(short, long, required = true)]
+    year: Option<usize>,
+
+    /// Rally slug, or enough words of the title to pick it out
+    #[arg(short, long, required = true)]
+    rally: Option<String>,
+
+    /// Car number or name of the driver the comparison is for
+    #[arg(short, long, required = true)]
+    driver: Option<String>,
+
+    /// Car numbers or names to compare against
+    #[arg(short, long, value_delimiter = ',')]
+    benchmarks: Vec<String>,
+
+    /// Also compare against the suggested benchmarks: leaders, neighbours in class and similar pace
+    #[arg(short, long)]
+    auto: bool,
+
+    /// Add a sheet comparing times on the same stages in the other years in the data dir
+    #[arg(long)]
+    history: bool,
+
+    /// Add a sheet with everyone in the driver's class, or class and category
+    #[arg(long, value_name = "class|category")]
+    field: Option<FieldScope>,
+
+    /// Round times to seconds, tenths, hundredths or thousandths, instead of showing them as published
+    #[arg(long)]
+    precision: Option<Precision>,
+
+    /// Write deltas and totals as formulas, so edits to times in the workbook carry through
+    #[arg(long)]
+    formulas: bool,
+
+    /// Directory holding the <year>rallies.json and uidsSmall.json files
+    #[arg(long, default_value = ".")]
+    data_dir: PathBuf,
+
+    #[arg(short, long, default_value = "timecomp.xlsx")]
+    output: PathBuf,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the rallies in a season, or the entrants in one rally
+    List {
+        #[arg(short, long)]
+        year: usize,
+
+        #[arg(short, long)]
+        rally: Option<String>,
+
+        #[arg(long, default_value = ".")]
+        data_dir: PathBuf,
+    },
+    /// Compare a driver against rivals over every rally of a season they both drove in
+    Season {
+        #[arg(short, long)]
+        year: usize,
+
+        /// Driver uid or name
+        #[arg(short, long)]
+        driver: String,
+
+        /// Rival uids or names
+        #[arg(short, long, value_delimiter = ',', required = true)]
+        rivals: Vec<String>,
+
+        #[arg(long, default_value = ".")]
+        data_dir: PathBuf,
+
+        #[arg(short, long, default_value = "season.xlsx")]
+        output: PathBuf,
+    },
+    /// Championship standings for a season, written as xlsx or json depending on the output's extension
+    Points {
+        #[arg(short, long)]
+        year: usize,
+
+        /// JSON points scheme, see PointsScheme. Anything left out takes the default.
+        #[arg(short, long)]
+        scheme: Option<PathBuf>,
+
+        #[arg(long, default_value = ".")]
+        data_dir: PathBuf,
+
+        #[arg(short, long, default_value = "points.xlsx")]
+        output: PathBuf,
+    },
+    /// Every rally someone has entered, as driver or codriver, over all the years in the data dir
+    History {
+        /// Uid or name
+        person: String,
+
+        #[arg(long, default_value = ".")]
+        data_dir: PathBuf,
+    },
+}
+
+/// A rally, entrant or person asked for on the command line that the data doesn't pick out
+#[derive(Debug)]
+enum LookupError {
+    UnknownRally(String),
+    UnknownEntrant(String, String),
+    UnknownDriver(String, usize),
+    UnknownPerson(String),
+    /// The query, what it was meant to pick out and everything it matched
+    Ambiguous(String, &'static str, Vec<String>),
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LookupError::UnknownRally(query) => write!(f, "No rally matching {}", query),
+            LookupError::UnknownEntrant(query, rally) => write!(f, "No entrant matching {} in {}", query, rally),
+            LookupError::UnknownDriver(query, year) => write!(f, "No driver matching {} in {}", query, year),
+            LookupError::UnknownPerson(query) => write!(f, "No one matching {}", query),
+            LookupError::Ambiguous(query, what, candidates) => write!(f, "{} matches several {}: {}", query, what, candidates.join(", ")),
+        }
     }
-    let rallies: Vec<structures::Rally> = spreadsheet::load_sneakattack_json("2025rallies.json").expect("oh no");
+}
 
-    let slug = "oregon_trail_rally_2025";
+impl Error for LookupError {}
+
+type YearRallies = (usize, Vec<structures::Rally>);
+
+fn data_file(data_dir: &Path, name: &str) -> String {
+    data_dir.join(name).to_string_lossy().into_owned()
+}
+
+/// The uids and each year's rallies, oldest first. Rallies that won't load are skipped with a
+/// warning, so one bad rally doesn't stop the rest, but a year's file has to be there.
+fn load(data_dir: &Path, years: &[usize]) -> Result<(UidMap, Vec<YearRallies>), DataError> {
+    let aliases = data::load_aliases(&data_file(data_dir, data::ALIASES_FILE))?;
+    let files: Vec<_> = years.iter().map(|year| (*year, data_file(data_dir, &format!("{}rallies.json", year)))).collect();
+    let files: Vec<_> = files.iter().map(|(year, file)| (*year, file.as_str())).collect();
+    let mut loaded = data::load(&data_file(data_dir, data::UIDS_FILE), &files, &aliases)?;
+    for (_, e) in loaded.errors {
+        if let DataError::Read { .. } | DataError::Syntax { .. } = e {
+            return Err(e);
+        }
+        eprintln!("warning: skipping {}", e);
+    }
+    for (_, rallies) in loaded.rallies.iter_mut() {
+        structures::sort_chronologically(rallies);
+    }
+    Ok((loaded.uids, loaded.rallies))
+}
+
+fn load_year(data_dir: &Path, year: usize) -> Result<(UidMap, Vec<structures::Rally>), DataError> {
+    let (uids, mut years) = load(data_dir, &[year])?;
+    Ok((uids, years.pop().map(|(_, rallies)| rallies).unwrap_or_default()))
+}
+
+/// Every year there's a <year>rallies.json for in the data dir, oldest first
+fn load_all_years(data_dir: &Path) -> Result<(UidMap, Vec<YearRallies>), Box<dyn Error>> {
+    let re = Regex::new(r"^(\d{4})rallies\.json$").unwrap();
+    let mut years = vec![];
+    for file in fs::read_dir(data_dir)? {
+        let name = file?.file_name();
+        if let Some(captures) = re.captures(&name.to_string_lossy()) {
+            years.push(captures[1].parse::<usize>()?);
+        }
+    }
+    years.sort();
+    Ok(load(data_dir, &years)?)
+}
+
+fn find_rally<'a>(rallies: &'a [structures::Rally], query: &str) -> Result<&'a structures::Rally, LookupError> {
+    match structures::find_rally(rallies, query).as_slice() {
+        [rally] => Ok(rally),
+        [] => Err(LookupError::UnknownRally(query.to_string())),
+        candidates => {
+            let slugs = candidates.iter().map(|r| r.slug.clone()).collect();
+            Err(LookupError::Ambiguous(query.to_string(), "rallies", slugs))
+        },
+    }
+}
 
-    let active = rallies.iter().filter(|i| i.slug == slug).next().unwrap();
+fn find_entry<'a>(rally: &'a structures::Rally, uids: &UidMap, query: &str) -> Result<&'a structures::Entry, LookupError> {
+    match rally.find_entry(uids, query).as_slice() {
+        [entry] => Ok(entry),
+        [] => Err(LookupError::UnknownEntrant(query.to_string(), rally.title.clone())),
+        candidates => {
+            let names = candidates.iter().map(|e| format!("#{} {}", e.number, e.names(uids))).collect();
+            Err(LookupError::Ambiguous(query.to_string(), "entrants", names))
+        },
+    }
+}
+
+fn person_names(candidates: &[&structures::Uid]) -> Vec<String> {
+    candidates.iter().map(|u| format!("{} {} ({})", u.first_name(), u.last_name(), u.uid)).collect()
+}
+
+fn find_driver<'a>(rallies: &[structures::Rally], uids: &'a UidMap, year: usize, query: &str) -> Result<&'a structures::Uid, LookupError> {
+    match structures::find_driver(rallies, uids, query).as_slice() {
+        [uid] => Ok(uid),
+        [] => Err(LookupError::UnknownDriver(query.to_string(), year)),
+        candidates => Err(LookupError::Ambiguous(query.to_string(), "drivers", person_names(candidates))),
+    }
+}
+
+fn season(data_dir: &Path, year: usize, driver: &str, rivals: &[String], output: &Path) -> Result<(), Box<dyn Error>> {
+    let (uids, rallies) = load_year(data_dir, year)?;
+    let driver = find_driver(&rallies, &uids, year, driver)?.uid;
+    let rivals = rivals.iter()
+        .map(|r| find_driver(&rallies, &uids, year, r.trim()).map(|u| u.uid))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let season = Season::build(year, &rallies, &uids, driver, &rivals)?;
+    let mut book = spreadsheet::build_season_workbook(&season)?;
+    book.save(output)?;
+    for rivalry in &season.rivals {
+        println!("{} rallies against {}", rivalry.rallies.len(), rivalry.name);
+    }
+    println!("Wrote {} {} to {}", season.driver, year, output.display());
+    Ok(())
+}
+
+fn points(data_dir: &Path, year: usize, scheme: Option<PathBuf>, output: &Path) -> Result<(), Box<dyn Error>> {
+    let (uids, rallies) = load_year(data_dir, year)?;
+    let scheme = match scheme {
+        Some(path) => serde_json::from_reader(fs::File::open(path)?)?,
+        None => PointsScheme::default(),
+    };
+
+    let championship = Championship::compute(&rallies, &uids, &scheme);
+    if output.extension().is_some_and(|e| e == "json") {
+        fs::write(output, serde_json::to_vec_pretty(&championship)?)?;
+    } else {
+        spreadsheet::build_championship_workbook(&championship)?.save(output)?;
+    }
+    for table in &championship.tables {
+        if let Some(leader) = table.standings.first() {
+            println!("{:<32} {} ({})", table.group, leader.name, leader.total);
+        }
+    }
+    println!("Wrote {} championship to {}", year, output.display());
+    Ok(())
+}
+
+fn history(data_dir: &Path, query: &str) -> Result<(), Box<dyn Error>> {
+    let (uids, years) = load_all_years(data_dir)?;
+    let history = History::build(years.iter()
+        .flat_map(|(year, rallies)| rallies.iter().map(|rally| (*year, rally))));
+
+    let person = match structures::find_uid(&uids, history.uids(), query).as_slice() {
+        [person] => *person,
+        [] => return Err(Box::new(LookupError::UnknownPerson(query.to_string()))),
+        candidates => return Err(Box::new(LookupError::Ambiguous(query.to_string(), "people", person_names(candidates)))),
+    };
+
+    println!("{} {} ({})", person.first_name(), person.last_name(), person.uid);
+    for appearance in history.for_uid(person.uid) {
+        let partner = uids.get(&appearance.partner)
+            .map(|p| format!("{} {}", p.first_name(), p.last_name()))
+            .unwrap_or_default();
+        let result = appearance.result.as_ref()
+            .and_then(spreadsheet::position_text)
+            .unwrap_or_else(|| "-".to_string());
+        println!("{}  {:<40} {:<8} {:>4}  {:<24} {:<6} {:<24} {:<8} {}",
+            appearance.year,
+            appearance.rally,
+            format!("{:?}", appearance.role),
+            appearance.number,
+            partner,
+            appearance.class,
+            appearance.category,
+            result,
+            appearance.model);
+    }
+    Ok(())
+}
+
+fn list(data_dir: &Path, year: usize, rally: Option<String>) -> Result<(), Box<dyn Error>> {
+    let (uids, rallies) = load_year(data_dir, year)?;
+
+    let Some(query) = rally else {
+        for rally in &rallies {
+            let date = rally.startDate.map(|d| d.to_string()).unwrap_or_default();
+            println!("{:<10}  {:<40} {} ({} entries)", date, rally.slug, rally.title, rally.entries.len());
+        }
+        return Ok(())
+    };
+
+    let rally = find_rally(&rallies, &query)?;
+    println!("{}", rally.title);
+    for entry in &rally.entries {
+        println!("{:>4}  {:<40} {:<6} {:<24} {}",
+            entry.number,
+            format!("{} / {}", entry.driver_name(&uids), entry.codriver_name(&uids)),
+            entry.class,
+            entry.category,
+            entry.model);
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    match args.command {
+        Some(Command::List { year, rally, data_dir }) => return list(&data_dir, year, rally),
+        Some(Command::Season { year, driver, rivals, data_dir, output }) => return season(&data_dir, year, &driver, &rivals, &output),
+        Some(Command::Points { year, scheme, data_dir, output }) => return points(&data_dir, year, scheme, &output),
+        Some(Command::History { person, data_dir }) => return history(&data_dir, &person),
+        None => (),
+    }
+
+    // clap enforces these whenever there's no subcommand
+    let (Some(year), Some(query), Some(driver)) = (args.year, args.rally, args.driver) else {
+        unreachable!()
+    };
+
+    let (uids, rallies) = load_year(&args.data_dir, year)?;
+    let active = find_rally(&rallies, &query)?;
+    let driver = find_entry(active, &uids, &driver)?.number;
+    let mut benchmarks = args.benchmarks.iter()
+        .map(|b| find_entry(active, &uids, b.trim()).map(|e| e.number))
+        .collect::<Result<Vec<_>, _>>()?;
+    if args.auto {
+        for suggestion in suggest::suggest_benchmarks(active, driver) {
+            let reasons: Vec<_> = suggestion.reasons.iter().map(|r| format!("{:?}", r)).collect();
+            println!("Suggested #{} ({})", suggestion.number, reasons.join(", "));
+            benchmarks.push(suggestion.number);
+        }
+    }
+
+    let mut comparison = Comparison::build(active, &uids, driver, &benchmarks, args.precision)?;
+    if args.history {
+        let (_, years) = load_all_years(&args.data_dir)?;
+        let others: Vec<_> = years.iter()
+            .flat_map(|(year, rallies)| rallies.iter().map(|rally| (*year, rally)))
+            .collect();
+        comparison.add_stage_history(year, active, &others);
+        println!("Found {} stages in other years", comparison.stage_history.len());
+    }
+    if let Some(scope) = args.field {
+        comparison.add_class_field(active, &uids, scope);
+    }
 
-    let data = spreadsheet::build_data(active, main_driver, &benchmark_drivers);
-    let mut book = spreadsheet::build_spreadsheet(active, &uids, main_driver, &benchmark_drivers).unwrap();
-    book.save("timecomp.xlsx");
+    let mut book = spreadsheet::build_workbook(&comparison, WorkbookOptions { formulas: args.formulas })?;
+    book.save(&args.output)?;
+    println!("Wrote {} to {}", active.title, args.output.display());
 
     Ok(())
 }
@@ -1,112 +1,1209 @@
-use tokio;
 use axum::{
-    body::Body,
-    routing::{get, post},
+    routing::get,
     http::{header, HeaderMap, StatusCode},
     Json, Router,
 };
-use serde::{Deserialize, Serialize};
-use axum::extract::{State, Form};
-use axum::response::{Html, IntoResponse};
-use axum_extra::extract::Query;
-use tower_http::services::ServeFile;
+use serde::{Deserialize, Deserializer, Serialize};
+use axum::extract::{Path, State};
+use axum::extract::rejection::PathRejection;
+use axum::response::{IntoResponse, Response};
+use axum::response::Html;
+use axum_extra::extract::{Query, QueryRejection};
+use askama::Template;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use sneakattackproto::spreadsheet;
-use sneakattackproto::structures::{self, UidMap};
+use sneakattackproto::comparison::{Comparison, FieldScope};
+use sneakattackproto::data::{self, ALIASES_FILE, RALLY_DATA, UIDS_FILE};
+use sneakattackproto::error::DataError;
+use sneakattackproto::export::{self, Format};
+use sneakattackproto::history::{Appearance, History};
+use sneakattackproto::points::{Championship, Grouping, PointsScheme};
+use sneakattackproto::spreadsheet::{self, WorkbookOptions};
+use sneakattackproto::structures::{self, Aliases, Category, Drivetrain, Precision, RallyStatus, Tier, UidMap};
+use sneakattackproto::suggest;
+use sneakattackproto::validate;
 use std::sync::OnceLock;
 use regex::Regex;
+use chrono::{Local, NaiveDate};
 
 // It'd be better if this was Cow or whatever
 #[derive(Clone)]
 struct RallyState {
     uids: HashMap<usize, structures::Uid>,
+    /// Read once at startup and used for every refresh after
+    aliases: Aliases,
+    /// In the order they should be displayed
+    years: Vec<usize>,
     rallies: HashMap<usize, HashMap<String, structures::Rally>>,
+    /// Slugs for each year, oldest rally first
+    order: HashMap<usize, Vec<String>>,
+    history: History,
 }
 
-// Keep these in the order you want them displayed in the web interface
-const RALLY_DATA: &[(usize, &'static str)] = &[
-    (2026, "2026rallies.json"),
-    (2025, "2025rallies.json"),
-    (2024, "2024rallies.json"),
-];
+/// How often to pull fresh results from upstream
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
-fn build_state() -> RallyState {
+impl RallyState {
+    fn new(uids: UidMap, aliases: Aliases, rally_data: Vec<(usize, Vec<structures::Rally>)>) -> Self {
+        let years = rally_data.iter().map(|(year, _)| *year).collect();
+        let mut rallies = HashMap::new();
+        let mut order = HashMap::new();
+        for (year, mut rallies_list) in rally_data {
+            structures::sort_chronologically(&mut rallies_list);
+            let mut data = HashMap::new();
+            order.insert(year, rallies_list.iter().map(|r| r.slug.clone()).collect());
+            for rally in rallies_list {
+                data.insert(rally.slug.clone(), rally);
+            }
+            rallies.insert(year, data);
+        }
+
+        let mut state = RallyState {
+            uids,
+            aliases,
+            years,
+            rallies,
+            order,
+            history: History::default(),
+        };
+        state.history = state.build_history();
+        state
+    }
+
+    fn build_history(&self) -> History {
+        History::build(self.years.iter().flat_map(|year| {
+            self.order[year].iter()
+                .filter_map(|slug| self.rallies[year].get(slug))
+                .map(|rally| (*year, rally))
+        }))
+    }
+}
+
+fn build_state() -> Result<RallyState, DataError> {
+    load_state(UIDS_FILE, ALIASES_FILE, RALLY_DATA)
+}
+
+/// Nothing works without the uids, but a rally file or a rally in one that won't load is
+/// reported and left out rather than stopping everything else being served
+fn load_state(uids_file: &str, aliases_file: &str, rally_data: &[(usize, &str)]) -> Result<RallyState, DataError> {
+    let aliases = data::load_aliases(aliases_file)?;
+    let loaded = data::load(uids_file, rally_data, &aliases)?;
+    for (year, e) in &loaded.errors {
+        eprintln!("load: {year} skipping {e}");
+    }
+    Ok(RallyState::new(loaded.uids, aliases, loaded.rallies))
+}
+
+/// The state handlers see. Refreshes build a whole new RallyState and swap it in, so a
+/// request always works against one consistent snapshot.
+#[derive(Clone)]
+struct SharedState(Arc<RwLock<Arc<RallyState>>>);
+
+impl SharedState {
+    fn new(state: RallyState) -> Self {
+        SharedState(Arc::new(RwLock::new(Arc::new(state))))
+    }
+
+    fn current(&self) -> Arc<RallyState> {
+        self.0.read().unwrap().clone()
+    }
 
-    let mut uids = UidMap::new();
-    let uids_list: Vec<structures::Uid> = spreadsheet::load_sneakattack_json("uidsSmall.json").unwrap();
-    for uid in uids_list {
-        uids.insert(uid.uid, uid);
+    fn replace(&self, state: RallyState) {
+        *self.0.write().unwrap() = Arc::new(state);
     }
+}
+
+/// Refetch the data, see data::fetch for what's allowed to fail, and swap it in. Rallies that
+/// won't load or fail validation keep whatever version we already had, so a half-published
+/// result doesn't take a rally offline. The new state is only built once they're settled, so
+/// they're ordered along with everything else.
+async fn refresh(shared: &SharedState, base: &str, uids_file: &str, rally_data: &[(usize, &str)]) -> Result<(), Box<dyn Error>> {
+    let current = shared.current();
+    let mut loaded = data::fetch(base, uids_file, rally_data, &current.aliases).await?;
+    let previous = |year: usize, slug: &str| current.rallies.get(&year).and_then(|r| r.get(slug)).cloned();
 
-    let mut rallies = HashMap::new();
-    for (year, data_file) in RALLY_DATA {
-        let mut data = HashMap::new();
-        let rallies_list: Vec<structures::Rally> = spreadsheet::load_sneakattack_json(data_file).unwrap();
-        for rally in rallies_list {
-            data.insert(rally.slug.clone(), rally);
+    for (year, rallies) in loaded.rallies.iter_mut() {
+        let fetched = std::mem::take(rallies);
+        for rally in fetched {
+            if validate::validate_rally(&rally, &loaded.uids).iter().all(|p| p.is_warning()) {
+                rallies.push(rally);
+                continue;
+            }
+            eprintln!("refresh: {year} {} failed validation, keeping the previous version", rally.slug);
+            rallies.extend(previous(*year, &rally.slug));
         }
-        rallies.insert(*year, data);
     }
 
-    RallyState {
-        uids,
-        rallies,
+    for (year, e) in &loaded.errors {
+        eprintln!("refresh: {year} {e}, keeping the previous version");
+        let restored = e.rally().and_then(|slug| previous(*year, slug));
+        if let (Some(restored), Some((_, rallies))) = (restored, loaded.rallies.iter_mut().find(|(y, _)| y == year)) {
+            rallies.push(restored);
+        }
     }
+
+    shared.replace(RallyState::new(loaded.uids, current.aliases.clone(), loaded.rallies));
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() {
-    let state = build_state();
+async fn refresh_forever(shared: SharedState) {
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    // The first tick fires straight away, and we've only just loaded from disk
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(e) = refresh(&shared, spreadsheet::SNEAK_ATTACK_BASE, UIDS_FILE, RALLY_DATA).await {
+            eprintln!("refresh failed: {e}");
+        }
+    }
+}
 
-    let app = Router::new()
-        .route_service("/", ServeFile::new("html/timecomp.html"))
+fn app(state: SharedState) -> Router {
+    Router::new()
+        .route("/", get(form))
         .route("/render", get(render_timecomp))
-        .with_state(state);
+        .route("/api/years", get(api_years))
+        .route("/api/rallies/{year}", get(api_rallies))
+        .route("/api/rallies/{year}/{slug}", get(api_rally))
+        .route("/api/rallies/{year}/{slug}/entries", get(api_entries))
+        .route("/api/compare", get(api_compare))
+        .route("/api/suggest", get(api_suggest))
+        .route("/api/history/{uid}", get(api_history))
+        .route("/api/points/{year}", get(api_points))
+        .with_state(state)
+}
+
+#[tokio::main]
+async fn main() {
+    let state = match build_state() {
+        Ok(state) => SharedState::new(state),
+        Err(e) => {
+            eprintln!("load failed: {e}");
+            std::process::exit(1);
+        },
+    };
+    tokio::spawn(refresh_forever(state.clone()));
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app(state)).await.unwrap();
+}
+
+#[derive(Debug)]
+enum WebError {
+    UnknownYear(usize),
+    UnknownRally(usize, String),
+    UnknownDriver(String, String),
+    UnknownUid(usize),
+    AmbiguousDriver(String, Vec<String>),
+    BadRequest(String),
+    Build(String),
+    Page(String),
+}
+
+impl WebError {
+    fn status(&self) -> StatusCode {
+        match self {
+            WebError::UnknownYear(_) |
+                WebError::UnknownRally(..) |
+                WebError::UnknownDriver(..) |
+                WebError::UnknownUid(_) => StatusCode::NOT_FOUND,
+            WebError::BadRequest(_) |
+                WebError::AmbiguousDriver(..) => StatusCode::BAD_REQUEST,
+            WebError::Build(_) |
+                WebError::Page(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for WebError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebError::UnknownYear(year) => write!(f, "No rallies for {year}"),
+            WebError::UnknownRally(year, slug) => write!(f, "No rally {slug} in {year}"),
+            WebError::UnknownDriver(driver, rally) => write!(f, "Driver {driver} did not race in {rally}"),
+            WebError::UnknownUid(uid) => write!(f, "No entries for {uid}"),
+            WebError::AmbiguousDriver(driver, candidates) => write!(f, "{driver} matches several entrants: {}", candidates.join(", ")),
+            WebError::BadRequest(msg) => write!(f, "Bad request: {msg}"),
+            WebError::Build(msg) => write!(f, "Failed to build spreadsheet: {msg}"),
+            WebError::Page(msg) => write!(f, "Failed to render page: {msg}"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    status: u16,
+    error: String,
+}
+
+impl IntoResponse for WebError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let body = ErrorBody {
+            status: status.as_u16(),
+            error: self.to_string(),
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+impl From<QueryRejection> for WebError {
+    fn from(rejection: QueryRejection) -> Self {
+        WebError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for WebError {
+    fn from(rejection: PathRejection) -> Self {
+        WebError::BadRequest(rejection.body_text())
+    }
 }
 
+struct FormEvent {
+    /// year|slug, see parse_event
+    value: String,
+    title: String,
+    /// Picked when the page loads
+    selected: bool,
+}
+
+#[derive(Template)]
+#[template(path = "timecomp.html")]
+struct FormTemplate {
+    events: Vec<FormEvent>,
+    /// Every car number in any rally, for the benchmark checkboxes
+    numbers: Vec<usize>,
+}
+
+impl FormTemplate {
+    /// The most recent rally to have started by `today` is picked to begin with
+    fn new(state: &RallyState, today: NaiveDate) -> Self {
+        let mut events = vec![];
+        let mut numbers = BTreeSet::new();
+        let mut picked = false;
+        for year in &state.years {
+            let rallies = &state.rallies[year];
+            // Newest first
+            for slug in state.order[year].iter().rev() {
+                let rally = &rallies[slug];
+                let selected = !picked && rally.status(today).is_some_and(|s| s != RallyStatus::Upcoming);
+                picked |= selected;
+                events.push(FormEvent {
+                    value: format!("{}|{}", year, slug),
+                    title: rally.title.clone(),
+                    selected,
+                });
+                numbers.extend(rally.entries.iter().map(|e| e.number));
+            }
+        }
+
+        FormTemplate {
+            events,
+            numbers: numbers.into_iter().collect(),
+        }
+    }
+}
+
+async fn form(State(state): State<SharedState>) -> Result<impl IntoResponse, WebError> {
+    let state = state.current();
+    let page = FormTemplate::new(&state, Local::now().date_naive()).render()
+        .map_err(|e| WebError::Page(e.to_string()))?;
+    Ok(Html(page))
+}
+
+/// Drivers and benchmarks can be given as car numbers or names, see Rally::find_entry
 #[derive(Deserialize, Debug)]
-#[allow(dead_code)]
 struct TimeComp {
-    driver: usize,
-    benchmarks: Vec<usize>,
+    driver: String,
+    /// Each value may hold several comma separated benchmarks
+    #[serde(default)]
+    benchmarks: Vec<String>,
+    /// Add the suggested benchmarks to any picked by hand
+    #[serde(default)]
+    auto: bool,
+    /// Look for the rally's stages in other years
+    #[serde(default)]
+    history: bool,
+    /// Live formulas for deltas and totals in the workbook
+    #[serde(default)]
+    formulas: bool,
+    /// Add everyone in the driver's class, or class and category
+    #[serde(default, deserialize_with = "empty_as_none")]
+    field: Option<FieldScope>,
+    /// Round every time to this, otherwise they're as published
+    #[serde(default, deserialize_with = "empty_as_none")]
+    precision: Option<Precision>,
     event: String,
+    #[serde(default)]
+    format: Format,
+}
+
+/// Selects send an empty string when nothing is picked
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+{
+    let value = String::deserialize(deserializer)?;
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value.parse().map(Some).map_err(serde::de::Error::custom)
 }
 
-async fn render_timecomp(input: Query<TimeComp>, State(state): State<RallyState>) -> Result<impl IntoResponse, (StatusCode, String)> {
-    // Split up year and slug
+/// Split up the year|slug the form sends as an event
+fn parse_event(event: &str) -> Result<(usize, &str), WebError> {
     static REGEX: OnceLock<regex::Regex> = OnceLock::new();
     let re = REGEX.get_or_init(|| { Regex::new(r"^(\d+)\|(.+)$").unwrap() });
 
-    let (_, [year, slug]) = re.captures_iter(&input.event)
+    let (_, [year, slug]) = re.captures_iter(event)
         .map(|c| c.extract()).next()
-        .ok_or((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to parse year|slug"),
-            ))?;
+        .ok_or_else(|| WebError::BadRequest(format!("Failed to parse year|slug from {event}")))?;
     let year: usize = year.parse()
-        .map_err(|_| (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to parse year {year}"),
-        ))?;
-
-    let active = &state.rallies[&year][slug];
+        .map_err(|_| WebError::BadRequest(format!("Failed to parse year {year}")))?;
+    Ok((year, slug))
+}
 
-    let mut book = spreadsheet::build_spreadsheet(&active, &state.uids, input.driver, &input.benchmarks).map_err(|e| (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to build spreadsheet: {e}"),
-    ))?;
-    let buf = book.save_to_buffer().unwrap();
+async fn render_timecomp(input: Result<Query<TimeComp>, QueryRejection>, State(state): State<SharedState>) -> Result<impl IntoResponse, WebError> {
+    let state = state.current();
+    let Query(input) = input?;
+    let (year, slug) = parse_event(&input.event)?;
+    let active = lookup_rally(&state, year, slug)?;
+    let (driver, comparison) = build_comparison(&state, year, active, &input)?;
+    let buf = export::render(&comparison, input.format, WorkbookOptions { formulas: input.formulas })
+        .map_err(|e| WebError::Build(e.to_string()))?;
 
-    let content_disposition_header = format!("attachment; filename=\"{}_{}.xlsx\"", &input.event, &input.driver);
+    let content_disposition_header = format!("attachment; filename=\"{}_{}.{}\"", &input.event, driver, input.format.extension());
 
     let mut headers = HeaderMap::new();
-    headers.insert(header::CONTENT_TYPE, "text/toml; charset=utf-8".parse().unwrap());
+    headers.insert(header::CONTENT_TYPE, input.format.content_type().parse().unwrap());
     headers.insert(header::CONTENT_DISPOSITION, content_disposition_header.parse().unwrap());
 
     Ok((headers, buf))
 }
+
+fn lookup_rally<'a>(state: &'a RallyState, year: usize, slug: &str) -> Result<&'a structures::Rally, WebError> {
+    state.rallies.get(&year)
+        .ok_or(WebError::UnknownYear(year))?
+        .get(slug)
+        .ok_or_else(|| WebError::UnknownRally(year, slug.to_string()))
+}
+
+/// Find the one entrant a car number or name refers to
+fn lookup_entry<'a>(state: &RallyState, rally: &'a structures::Rally, query: &str) -> Result<&'a structures::Entry, WebError> {
+    match rally.find_entry(&state.uids, query).as_slice() {
+        [entry] => Ok(entry),
+        [] => Err(WebError::UnknownDriver(query.to_string(), rally.title.clone())),
+        candidates => Err(WebError::AmbiguousDriver(
+            query.to_string(),
+            candidates.iter().map(|e| format!("#{} {}", e.number, e.names(&state.uids))).collect(),
+        )),
+    }
+}
+
+/// Turn the driver and benchmarks into car numbers. Benchmark numbers that aren't in this
+/// rally are skipped since the form offers every number, but names have to match someone.
+fn resolve_entrants(state: &RallyState, rally: &structures::Rally, input: &TimeComp) -> Result<(usize, Vec<usize>), WebError> {
+    let driver = lookup_entry(state, rally, &input.driver)?.number;
+
+    let mut benchmarks = vec![];
+    for query in input.benchmarks.iter().flat_map(|b| b.split(',')).map(str::trim) {
+        if query.is_empty() {
+            continue
+        }
+        match query.parse() {
+            Ok(number) => benchmarks.push(number),
+            Err(_) => benchmarks.push(lookup_entry(state, rally, query)?.number),
+        }
+    }
+    if input.auto {
+        benchmarks.extend(suggest::suggest_benchmarks(rally, driver).iter().map(|s| s.number));
+    }
+    Ok((driver, benchmarks))
+}
+
+/// Returns the driver's car number along with the comparison
+fn build_comparison(state: &RallyState, year: usize, rally: &structures::Rally, input: &TimeComp) -> Result<(usize, Comparison), WebError> {
+    let (driver, benchmarks) = resolve_entrants(state, rally, input)?;
+    let mut comparison = Comparison::build(rally, &state.uids, driver, &benchmarks, input.precision)
+        .map_err(|e| WebError::Build(e.to_string()))?;
+
+    if input.history {
+        let others: Vec<_> = state.rallies.iter()
+            .flat_map(|(y, rallies)| rallies.values().map(|r| (*y, r)))
+            .collect();
+        comparison.add_stage_history(year, rally, &others);
+    }
+    if let Some(scope) = input.field {
+        comparison.add_class_field(rally, &state.uids, scope);
+    }
+    Ok((driver, comparison))
+}
+
+#[derive(Serialize)]
+struct RallySummary {
+    slug: String,
+    title: String,
+    start_date: Option<NaiveDate>,
+    finish_date: Option<NaiveDate>,
+    status: Option<RallyStatus>,
+    stages: usize,
+    entries: usize,
+}
+
+/// Only rallies with a day between from and to, inclusive, and with the given status today
+#[derive(Deserialize, Debug)]
+struct RalliesQuery {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    status: Option<RallyStatus>,
+}
+
+#[derive(Serialize)]
+struct EntrySummary {
+    driver: String,
+    codriver: String,
+    drivetrain: Option<Drivetrain>,
+    tier: Option<Tier>,
+    #[serde(flatten)]
+    entry: structures::Entry,
+}
+
+/// Narrow the entry list down by what the classes are
+#[derive(Deserialize, Debug)]
+struct EntriesQuery {
+    drivetrain: Option<Drivetrain>,
+    tier: Option<Tier>,
+}
+
+async fn api_years(State(state): State<SharedState>) -> Json<Vec<usize>> {
+    let state = state.current();
+    Json(state.years.clone())
+}
+
+/// Oldest first, with their status as of `today`
+fn rally_summaries(state: &RallyState, year: usize, query: &RalliesQuery, today: NaiveDate) -> Result<Vec<RallySummary>, WebError> {
+    let rallies = state.rallies.get(&year)
+        .ok_or(WebError::UnknownYear(year))?;
+    let summaries = state.order[&year].iter()
+        .filter_map(|slug| rallies.get(slug))
+        .filter(|rally| rally.within(query.from, query.to))
+        .filter(|rally| query.status.is_none() || rally.status(today) == query.status)
+        .map(|rally| RallySummary {
+            slug: rally.slug.clone(),
+            title: rally.title.clone(),
+            start_date: rally.startDate,
+            finish_date: rally.finishDate,
+            status: rally.status(today),
+            stages: rally.stages.len(),
+            entries: rally.entries.len(),
+        })
+        .collect();
+    Ok(summaries)
+}
+
+async fn api_rallies(year: Result<Path<usize>, PathRejection>, query: Result<Query<RalliesQuery>, QueryRejection>, State(state): State<SharedState>) -> Result<impl IntoResponse, WebError> {
+    let state = state.current();
+    let Path(year) = year?;
+    let Query(query) = query?;
+    Ok(Json(rally_summaries(&state, year, &query, Local::now().date_naive())?))
+}
+
+async fn api_rally(path: Result<Path<(usize, String)>, PathRejection>, State(state): State<SharedState>) -> Result<impl IntoResponse, WebError> {
+    let state = state.current();
+    let Path((year, slug)) = path?;
+    let rally = lookup_rally(&state, year, &slug)?;
+    Ok(Json(rally.clone()))
+}
+
+async fn api_entries(path: Result<Path<(usize, String)>, PathRejection>, query: Result<Query<EntriesQuery>, QueryRejection>, State(state): State<SharedState>) -> Result<impl IntoResponse, WebError> {
+    let state = state.current();
+    let Path((year, slug)) = path?;
+    let Query(query) = query?;
+    let rally = lookup_rally(&state, year, &slug)?;
+    let entries: Vec<_> = rally.entries.iter()
+        .filter(|entry| query.drivetrain.is_none_or(|d| entry.class.drivetrain() == Some(d)))
+        .filter(|entry| query.tier.is_none_or(|t| entry.class.tier() == Some(t)))
+        .map(|entry| EntrySummary {
+            driver: entry.driver_name(&state.uids),
+            codriver: entry.codriver_name(&state.uids),
+            drivetrain: entry.class.drivetrain(),
+            tier: entry.class.tier(),
+            entry: entry.clone(),
+        })
+        .collect();
+
+    Ok(Json(entries))
+}
+
+async fn api_compare(input: Result<Query<TimeComp>, QueryRejection>, State(state): State<SharedState>) -> Result<impl IntoResponse, WebError> {
+    let state = state.current();
+    let Query(input) = input?;
+    let (year, slug) = parse_event(&input.event)?;
+    let rally = lookup_rally(&state, year, slug)?;
+    let (_, comparison) = build_comparison(&state, year, rally, &input)?;
+
+    Ok(Json(comparison))
+}
+
+#[derive(Deserialize, Debug)]
+struct SuggestQuery {
+    driver: String,
+    event: String,
+}
+
+async fn api_suggest(input: Result<Query<SuggestQuery>, QueryRejection>, State(state): State<SharedState>) -> Result<impl IntoResponse, WebError> {
+    let state = state.current();
+    let Query(input) = input?;
+    let (year, slug) = parse_event(&input.event)?;
+    let rally = lookup_rally(&state, year, slug)?;
+    let driver = lookup_entry(&state, rally, &input.driver)?.number;
+
+    Ok(Json(suggest::suggest_benchmarks(rally, driver)))
+}
+
+#[derive(Serialize)]
+struct HistoryResponse {
+    uid: usize,
+    name: String,
+    appearances: Vec<Appearance>,
+}
+
+async fn api_history(uid: Result<Path<usize>, PathRejection>, State(state): State<SharedState>) -> Result<impl IntoResponse, WebError> {
+    let state = state.current();
+    let Path(uid) = uid?;
+    let appearances = state.history.for_uid(uid);
+    if appearances.is_empty() {
+        return Err(WebError::UnknownUid(uid));
+    }
+
+    Ok(Json(HistoryResponse {
+        uid,
+        name: structures::full_name(&state.uids, uid),
+        appearances: appearances.to_vec(),
+    }))
+}
+
+/// Tweaks to the default points scheme
+#[derive(Deserialize, Debug)]
+struct PointsQuery {
+    grouping: Option<Grouping>,
+    best_of: Option<usize>,
+    drivetrain: Option<Drivetrain>,
+    tier: Option<Tier>,
+    /// Only these categories score, everyone does if there are none
+    #[serde(default)]
+    category: Vec<Category>,
+    /// slug:multiplier, one for each rally that isn't worth the usual points
+    #[serde(default)]
+    coefficient: Vec<String>,
+}
+
+/// Split up the slug:multiplier a coefficient is given as, slugs can have colons of their own
+fn parse_coefficient(coefficient: &str) -> Result<(String, f32), WebError> {
+    coefficient.rsplit_once(':')
+        .and_then(|(slug, multiplier)| Some((slug.to_string(), multiplier.parse().ok()?)))
+        .ok_or_else(|| WebError::BadRequest(format!("Failed to parse slug:multiplier from {coefficient}")))
+}
+
+async fn api_points(year: Result<Path<usize>, PathRejection>, query: Result<Query<PointsQuery>, QueryRejection>, State(state): State<SharedState>) -> Result<impl IntoResponse, WebError> {
+    let state = state.current();
+    let Path(year) = year?;
+    let Query(query) = query?;
+    state.rallies.get(&year)
+        .ok_or(WebError::UnknownYear(year))?;
+    let rallies: Vec<_> = state.order[&year].iter()
+        .filter_map(|slug| state.rallies[&year].get(slug))
+        .cloned()
+        .collect();
+
+    let mut scheme = PointsScheme::default();
+    if let Some(grouping) = query.grouping {
+        scheme.grouping = grouping;
+    }
+    scheme.best_of = query.best_of;
+    scheme.drivetrain = query.drivetrain;
+    scheme.tier = query.tier;
+    if !query.category.is_empty() {
+        scheme.categories = Some(query.category);
+    }
+    scheme.coefficients = query.coefficient.iter()
+        .map(|c| parse_coefficient(c))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Json(Championship::compute(&rallies, &state.uids, &scheme)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{self, Body};
+    use axum::http::Request;
+    use tower::ServiceExt;
+    use tower_http::services::ServeFile;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+    /// State loaded from the named fixture files, along with the fixture uids
+    fn load_fixtures(files: &[(usize, &str)]) -> RallyState {
+        let paths: Vec<_> = files.iter().map(|(year, file)| (*year, format!("{FIXTURES}/{file}"))).collect();
+        let paths: Vec<_> = paths.iter().map(|(year, path)| (*year, path.as_str())).collect();
+        load_state(&format!("{FIXTURES}/uids.json"), &format!("{FIXTURES}/aliases.json"), &paths).unwrap()
+    }
+
+    fn fixture_state_with(files: &[(usize, &str)]) -> SharedState {
+        SharedState::new(load_fixtures(files))
+    }
+
+    fn fixture_state() -> SharedState {
+        fixture_state_with(&[(2025, "rallies.json")])
+    }
+
+    fn fixture_app_with(files: &[(usize, &str)]) -> Router {
+        app(fixture_state_with(files))
+    }
+
+    fn fixture_app() -> Router {
+        app(fixture_state())
+    }
+
+    /// Stand in for sneakattackrally.com, serving the fixtures under the upstream names
+    async fn upstream_stub(rallies: &str) -> String {
+        let stub = Router::new()
+            .route_service("/uidsSmall.json", ServeFile::new(format!("{FIXTURES}/uids.json")))
+            .route_service("/2025rallies.json", ServeFile::new(format!("{FIXTURES}/{rallies}")));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, stub).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    /// Status, content type and body of a GET against `app`
+    async fn request(app: &Router, uri: &str) -> (StatusCode, String, Vec<u8>) {
+        let response = app.clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap().to_string();
+        let body = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, content_type, body.to_vec())
+    }
+
+    async fn get_with_type(uri: &str) -> (StatusCode, String, Vec<u8>) {
+        request(&fixture_app(), uri).await
+    }
+
+    async fn get(uri: &str) -> (StatusCode, Vec<u8>) {
+        let (status, _, body) = get_with_type(uri).await;
+        (status, body)
+    }
+
+    /// A successful response from `app`, as JSON
+    async fn get_json_from(app: &Router, uri: &str) -> serde_json::Value {
+        let (status, _, body) = request(app, uri).await;
+        assert_eq!(status, StatusCode::OK);
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    async fn get_json(uri: &str) -> serde_json::Value {
+        get_json_from(&fixture_app(), uri).await
+    }
+
+    /// The message from an API error response, checking the status it came with
+    async fn get_error(uri: &str, expected: StatusCode) -> String {
+        let (status, body) = get(uri).await;
+        assert_eq!(status, expected);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], expected.as_u16());
+        body["error"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn form_lists_rallies_and_numbers() {
+        let (status, content_type, body) = get_with_type("/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(content_type.starts_with("text/html"));
+        let body = String::from_utf8(body).unwrap();
+        // Newest first, and picked as it's already happened
+        let collision = body.find(r#"<option value="2025|SS1 Collision" selected>Sheet Name Collision</option>"#).unwrap();
+        let test_rally = body.find(r#"<option value="2025|test_rally">Test Rally</option>"#).unwrap();
+        assert!(collision < test_rally);
+        for number in [10, 20, 30] {
+            assert!(body.contains(&format!(r#"id="benchmark{number}""#)));
+        }
+    }
+
+    #[tokio::test]
+    async fn render_succeeds() {
+        let (status, content_type, body) = get_with_type("/render?event=2025|test_rally&driver=10&benchmarks=20&benchmarks=30").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, Format::Xlsx.content_type());
+        // xlsx files are zip archives
+        assert_eq!(&body[..2], b"PK");
+    }
+
+    #[tokio::test]
+    async fn render_formulas() {
+        let (status, _, body) = get_with_type("/render?event=2025|test_rally&driver=10&benchmarks=20&formulas=true").await;
+        assert_eq!(status, StatusCode::OK);
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+        let mut overview = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("xl/worksheets/sheet1.xml").unwrap(), &mut overview).unwrap();
+        assert!(overview.contains("<f>IF(AND(ISNUMBER("));
+        assert!(overview.contains("<f>SUM("));
+    }
+
+    #[tokio::test]
+    async fn render_charts() {
+        let (status, _, body) = get_with_type("/render?event=2025|test_rally&driver=10&benchmarks=20,30").await;
+        assert_eq!(status, StatusCode::OK);
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+        let mut workbook = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("xl/workbook.xml").unwrap(), &mut workbook).unwrap();
+        assert!(workbook.contains(r#"<sheet name="Charts""#));
+        // Cumulative gap, pace per stage and sectors of the one stage with splits
+        for chart in 1..=3 {
+            assert!(archive.by_name(&format!("xl/charts/chart{chart}.xml")).is_ok());
+        }
+        assert!(archive.by_name("xl/charts/chart4.xml").is_err());
+    }
+
+    #[tokio::test]
+    async fn compare_class_field() {
+        let body = get_json("/api/compare?event=2025|test_rally&driver=10&field=class").await;
+        let field = body["class_field"].as_array().unwrap();
+        // Finishing order, then the retirement
+        let numbers: Vec<_> = field.iter().map(|e| e["number"].as_u64().unwrap()).collect();
+        assert_eq!(numbers, [20, 10, 30]);
+        assert_eq!(field[1]["stages"][0]["delta"], serde_json::Value::Null);
+        assert_eq!(field[0]["stages"][0]["delta"]["kind"], "Slower");
+
+        let body = get_json("/api/compare?event=2025|test_rally&driver=10&field=category").await;
+        let numbers: Vec<_> = body["class_field"].as_array().unwrap().iter().map(|e| e["number"].as_u64().unwrap()).collect();
+        assert_eq!(numbers, [20, 10]);
+    }
+
+    #[tokio::test]
+    async fn render_without_class_field() {
+        let (status, _, _) = get_with_type("/render?event=2025|test_rally&driver=10&field=").await;
+        assert_eq!(status, StatusCode::OK);
+        get_error("/render?event=2025|test_rally&driver=10&field=overall", StatusCode::BAD_REQUEST).await;
+    }
+
+    #[tokio::test]
+    async fn render_csv() {
+        let (status, content_type, body) = get_with_type("/render?event=2025|test_rally&driver=10&benchmarks=20&format=csv").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "application/zip");
+        assert_eq!(&body[..2], b"PK");
+    }
+
+    #[tokio::test]
+    async fn render_json() {
+        let (status, content_type, body) = get_with_type("/render?event=2025|test_rally&driver=10&benchmarks=20&format=json").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "application/json");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["driver"]["number"], 10);
+        assert_eq!(body["stages"][0]["benchmarks"][0]["delta"]["kind"], "Slower");
+    }
+
+    #[tokio::test]
+    async fn render_unknown_format() {
+        get_error("/render?event=2025|test_rally&driver=10&format=pdf", StatusCode::BAD_REQUEST).await;
+    }
+
+    #[tokio::test]
+    async fn render_unknown_year() {
+        let error = get_error("/render?event=1999|test_rally&driver=10", StatusCode::NOT_FOUND).await;
+        assert_eq!(error, "No rallies for 1999");
+    }
+
+    #[tokio::test]
+    async fn render_unknown_slug() {
+        let error = get_error("/render?event=2025|nope&driver=10", StatusCode::NOT_FOUND).await;
+        assert_eq!(error, "No rally nope in 2025");
+    }
+
+    #[tokio::test]
+    async fn render_unknown_driver() {
+        let error = get_error("/render?event=2025|test_rally&driver=99", StatusCode::NOT_FOUND).await;
+        assert_eq!(error, "Driver 99 did not race in Test Rally");
+    }
+
+    #[tokio::test]
+    async fn render_malformed_event() {
+        get_error("/render?event=test_rally&driver=10", StatusCode::BAD_REQUEST).await;
+    }
+
+    #[tokio::test]
+    async fn render_unknown_driver_name() {
+        let error = get_error("/render?event=2025|test_rally&driver=nobody", StatusCode::NOT_FOUND).await;
+        assert_eq!(error, "Driver nobody did not race in Test Rally");
+    }
+
+    #[tokio::test]
+    async fn render_ambiguous_driver_name() {
+        // Navigator and Notes
+        let error = get_error("/render?event=2025|test_rally&driver=n", StatusCode::BAD_REQUEST).await;
+        assert_eq!(error, "n matches several entrants: #10 Driver/Navigator, #30 Steady/Notes");
+    }
+
+    #[tokio::test]
+    async fn compare_by_name() {
+        let body = get_json("/api/compare?event=2025|test_rally&driver=Ada%20Driver&benchmarks=quikc,30&benchmarks=").await;
+        assert_eq!(body["driver"]["number"], 10);
+        let benchmarks: Vec<_> = body["benchmarks"].as_array().unwrap().iter().map(|b| b["number"].clone()).collect();
+        assert_eq!(benchmarks, vec![20, 30]);
+    }
+
+    #[tokio::test]
+    async fn render_build_failure() {
+        // The only split stage's sheet name collides with the overview sheet
+        let error = get_error("/render?event=2025|SS1%20Collision&driver=10", StatusCode::INTERNAL_SERVER_ERROR).await;
+        assert!(error.starts_with("Failed to build spreadsheet"));
+    }
+
+    #[test]
+    fn form_picks_latest_started_rally() {
+        let state = fixture_state().current();
+        let selected = |today: &str| {
+            let form = FormTemplate::new(&state, today.parse().unwrap());
+            form.events.into_iter().find(|e| e.selected).map(|e| e.value)
+        };
+        assert_eq!(selected("2025-05-20").as_deref(), Some("2025|test_rally"));
+        assert_eq!(selected("2025-06-01").as_deref(), Some("2025|SS1 Collision"));
+        assert_eq!(selected("2025-01-01"), None);
+    }
+
+    #[tokio::test]
+    async fn api_rallies_by_date() {
+        let slugs = |uri: &'static str| async move {
+            let body = get_json(uri).await;
+            body.as_array().unwrap().iter().map(|r| r["slug"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        };
+
+        // Oldest first
+        assert_eq!(slugs("/api/rallies/2025").await, vec!["test_rally", "SS1 Collision"]);
+        assert_eq!(slugs("/api/rallies/2025?from=2025-05-18").await, vec!["SS1 Collision"]);
+        assert_eq!(slugs("/api/rallies/2025?to=2025-05-16").await, vec!["test_rally"]);
+
+        let body = get_json("/api/rallies/2025").await;
+        assert_eq!(body[0]["start_date"], "2025-05-16");
+        assert_eq!(body[0]["finish_date"], "2025-05-17");
+        get_error("/api/rallies/2025?status=soon", StatusCode::BAD_REQUEST).await;
+    }
+
+    #[test]
+    fn rally_summaries_by_status() {
+        use RallyStatus::*;
+        let state = load_fixtures(&[(2025, "rallies.json")]);
+        let statuses = |today: &str, status| {
+            let query = RalliesQuery { from: None, to: None, status };
+            rally_summaries(&state, 2025, &query, today.parse().unwrap()).unwrap().into_iter()
+                .map(|r| (r.slug, r.status.unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        let (rally, collision) = ("test_rally".to_string(), "SS1 Collision".to_string());
+        assert_eq!(statuses("2025-05-01", None), vec![(rally.clone(), Upcoming), (collision.clone(), Upcoming)]);
+        assert_eq!(statuses("2025-05-17", None), vec![(rally.clone(), InProgress), (collision.clone(), Upcoming)]);
+        assert_eq!(statuses("2025-05-18", Some(Finished)), vec![(rally.clone(), Finished)]);
+        assert_eq!(statuses("2025-05-18", Some(Upcoming)), vec![(collision.clone(), Upcoming)]);
+        assert_eq!(statuses("2025-06-02", Some(Finished)), vec![(rally, Finished), (collision, Finished)]);
+        assert!(statuses("2025-06-02", Some(InProgress)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn api_unknown_year() {
+        get_error("/api/rallies/1999", StatusCode::NOT_FOUND).await;
+    }
+
+    #[tokio::test]
+    async fn api_malformed_year() {
+        get_error("/api/rallies/latest", StatusCode::BAD_REQUEST).await;
+    }
+
+    #[tokio::test]
+    async fn api_unknown_slug() {
+        get_error("/api/rallies/2025/nope/entries", StatusCode::NOT_FOUND).await;
+    }
+
+    #[tokio::test]
+    async fn api_entries_unknown_uid() {
+        let mut state = load_fixtures(&[(2025, "rallies.json")]);
+        state.uids.remove(&5);
+        let body = get_json_from(&app(SharedState::new(state)), "/api/rallies/2025/test_rally/entries").await;
+        let entry = body.as_array().unwrap().iter().find(|e| e["driverUID"] == 5).unwrap();
+        assert_eq!(entry["driver"], "Unknown (5)");
+        assert_ne!(entry["codriver"], "Unknown (6)");
+    }
+
+    #[tokio::test]
+    async fn api_compare_unknown_driver() {
+        get_error("/api/compare?event=2025|test_rally&driver=99", StatusCode::NOT_FOUND).await;
+    }
+
+    #[tokio::test]
+    async fn refresh_swaps_in_new_rallies() {
+        let base = upstream_stub("refreshed.json").await;
+        let shared = fixture_state();
+        refresh(&shared, &base, UIDS_FILE, &[(2025, "2025rallies.json")]).await.unwrap();
+
+        let state = shared.current();
+        let rallies = &state.rallies[&2025];
+        assert!(rallies.contains_key("new_rally"));
+        // Only exists in the original data
+        assert!(!rallies.contains_key("SS1 Collision"));
+        // The refreshed copy is truncated, so the one we had is kept
+        assert_eq!(rallies["test_rally"].entries.len(), 3);
+        assert_eq!(state.order[&2025], vec!["test_rally", "new_rally"]);
+    }
+
+    #[tokio::test]
+    async fn refresh_keeps_rallies_that_fail_to_parse() {
+        let base = upstream_stub("malformed.json").await;
+        let loaded = load_fixtures(&[(2025, "malformed.json")]);
+        let sprint = loaded.rallies[&2025]["sprint"].clone();
+        let mut broken = sprint.clone();
+        broken.slug = "broken_sprint".to_string();
+        let shared = SharedState::new(RallyState::new(loaded.uids, loaded.aliases, vec![(2025, vec![sprint, broken])]));
+        refresh(&shared, &base, UIDS_FILE, &[(2025, "2025rallies.json")]).await.unwrap();
+
+        // Restored, and listed wherever the order is used
+        let state = shared.current();
+        assert!(state.rallies[&2025].contains_key("broken_sprint"));
+        let query = RalliesQuery { from: None, to: None, status: None };
+        let slugs: Vec<_> = rally_summaries(&state, 2025, &query, "2025-08-03".parse().unwrap()).unwrap()
+            .into_iter().map(|r| r.slug).collect();
+        assert_eq!(slugs.len(), 2);
+        assert!(slugs.contains(&"broken_sprint".to_string()));
+        assert_eq!(state.history.for_uid(1).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn refresh_drops_new_invalid_rallies() {
+        let base = upstream_stub("refreshed.json").await;
+        let shared = fixture_state_with(&[(2025, "sprint.json")]);
+        refresh(&shared, &base, UIDS_FILE, &[(2025, "2025rallies.json")]).await.unwrap();
+
+        // The truncated test_rally is new to us, so there's nothing to keep in its place
+        let state = shared.current();
+        assert!(!state.rallies[&2025].contains_key("test_rally"));
+        assert_eq!(state.order[&2025], vec!["new_rally"]);
+        let form = FormTemplate::new(&state, "2025-08-01".parse().unwrap());
+        assert_eq!(form.events.iter().map(|e| e.value.as_str()).collect::<Vec<_>>(), vec!["2025|new_rally"]);
+    }
+
+    #[tokio::test]
+    async fn refresh_failure_keeps_state() {
+        let base = upstream_stub("refreshed.json").await;
+        let shared = fixture_state();
+        let result = refresh(&shared, &base, UIDS_FILE, &[(2025, "2025rallies.json"), (2026, "2026rallies.json")]).await;
+        assert!(result.is_err());
+
+        let state = shared.current();
+        assert_eq!(state.years, vec![2025]);
+        assert!(!state.rallies[&2025].contains_key("new_rally"));
+    }
+
+    #[tokio::test]
+    async fn suggest_benchmarks() {
+        let body = get_json("/api/suggest?event=2025|test_rally&driver=10").await;
+        assert_eq!(body, serde_json::json!([
+            {"number": 20, "reasons": ["OverallLeader", "ClassLeader", "AheadInClass", "SimilarPace"]},
+            {"number": 30, "reasons": ["SimilarPace"]},
+        ]));
+    }
+
+    #[tokio::test]
+    async fn compare_auto() {
+        let body = get_json("/api/compare?event=2025|test_rally&driver=30&auto=true").await;
+        let benchmarks: Vec<_> = body["benchmarks"].as_array().unwrap().iter().map(|b| b["number"].clone()).collect();
+        assert_eq!(benchmarks, vec![10, 20]);
+    }
+
+    #[tokio::test]
+    async fn history_for_uid() {
+        let body = get_json("/api/history/2").await;
+        assert_eq!(body["name"], "Bea Navigator");
+        let appearances = body["appearances"].as_array().unwrap();
+        assert_eq!(appearances.len(), 2);
+        assert_eq!(appearances[0]["slug"], "test_rally");
+        assert_eq!(appearances[0]["role"], "Codriver");
+        assert_eq!(appearances[0]["partner"], 1);
+        assert_eq!(appearances[0]["result"]["overall"], 2);
+    }
+
+    #[tokio::test]
+    async fn history_unknown_uid() {
+        get_error("/api/history/99", StatusCode::NOT_FOUND).await;
+    }
+
+    #[tokio::test]
+    async fn history_missing_from_uids() {
+        let mut state = load_fixtures(&[(2025, "rallies.json")]);
+        state.uids.remove(&2);
+        let body = get_json_from(&app(SharedState::new(state)), "/api/history/2").await;
+        assert_eq!(body["name"], "Unknown (2)");
+        assert_eq!(body["appearances"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn compare_stage_history() {
+        let app = fixture_app_with(&[(2025, "rallies.json"), (2024, "history.json")]);
+        let body = get_json_from(&app, "/api/compare?event=2025|test_rally&driver=10&benchmarks=20&history=true").await;
+
+        let history = body["stage_history"].as_array().unwrap();
+        assert_eq!(history.len(), 2);
+        // Ridge is run twice in 2024, and reversed once which is a different stage
+        let ridge = history[1]["runs"].as_array().unwrap();
+        let years: Vec<_> = ridge.iter().map(|r| r["year"].clone()).collect();
+        assert_eq!(years, vec![2024, 2024, 2025]);
+        assert_eq!(ridge[0]["driver"], "03:05.0");
+        // Matched by uid, they were #21 that year
+        assert_eq!(ridge[0]["benchmarks"][0]["number"], 21);
+        assert_eq!(ridge[0]["benchmarks"][0]["delta"]["kind"], "Slower");
+        // Missed the second pass
+        assert_eq!(ridge[1]["benchmarks"][0], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn compare_precision() {
+        let app = fixture_app_with(&[(2025, "sprint.json")]);
+
+        // Times come back out exactly as they went in
+        let rally = get_json_from(&app, "/api/rallies/2025/sprint").await;
+        assert_eq!(rally["entries"][0]["times"][0]["time"], "01:02.34");
+        assert_eq!(rally["entries"][1]["times"][0]["time"], "01:02.365");
+
+        let body = get_json_from(&app, "/api/compare?event=2025|sprint&driver=10&benchmarks=20").await;
+        assert_eq!(body["precision"], serde_json::Value::Null);
+        assert_eq!(body["stages"][0]["benchmarks"][0]["time"], "01:02.365");
+        assert_eq!(body["stages"][0]["benchmarks"][0]["delta"]["kind"], "Faster");
+
+        // Rounded half up before anything is worked out from them
+        let body = get_json_from(&app, "/api/compare?event=2025|sprint&driver=10&benchmarks=20&precision=tenths").await;
+        assert_eq!(body["precision"], "tenths");
+        assert_eq!(body["stages"][0]["driver"]["time"], "01:02.3");
+        assert_eq!(body["stages"][0]["benchmarks"][0]["time"], "01:02.4");
+
+        let body = get_json_from(&app, "/api/compare?event=2025|sprint&driver=10&benchmarks=20&precision=hundredths").await;
+        assert_eq!(body["stages"][0]["benchmarks"][0]["time"], "01:02.37");
+        assert_eq!(body["driver"]["result"]["total"], "01:02.34");
+    }
+
+    #[test]
+    fn load_skips_malformed_rallies() {
+        let state = load_fixtures(&[(2025, "malformed.json")]);
+        assert!(state.rallies[&2025].contains_key("sprint"));
+        assert!(!state.rallies[&2025].contains_key("broken_sprint"));
+    }
+
+    #[test]
+    fn load_reports_missing_uids() {
+        let rallies = format!("{FIXTURES}/rallies.json");
+        let error = load_state("nope.json", ALIASES_FILE, &[(2025, &rallies)]).err().unwrap();
+        assert!(error.to_string().starts_with("nope.json: "));
+    }
+
+    #[tokio::test]
+    async fn entries_classes() {
+        let app = fixture_app_with(&[(2025, "classes.json")]);
+        let field = |body: &serde_json::Value, name: &str| -> Vec<serde_json::Value> {
+            body.as_array().unwrap().iter().map(|e| e[name].clone()).collect()
+        };
+
+        // Both spellings of Class X, and anything we've not heard of kept as published
+        let body = get_json_from(&app, "/api/rallies/2025/classes/entries").await;
+        assert_eq!(field(&body, "class"), vec!["ClassX", "ClassX", "G2", "O4WD"]);
+        assert_eq!(field(&body, "category"), vec!["National", "Regional", "Dual", "AraRallySprint"]);
+        assert_eq!(field(&body, "drivetrain"), vec![serde_json::Value::Null, serde_json::Value::Null, serde_json::Value::Null, "4WD".into()]);
+
+        let body = get_json_from(&app, "/api/rallies/2025/classes/entries?drivetrain=4WD").await;
+        assert_eq!(field(&body, "number"), vec![4]);
+        let body = get_json_from(&app, "/api/rallies/2025/classes/entries?tier=Limited").await;
+        assert_eq!(field(&body, "number"), Vec::<serde_json::Value>::new());
+
+        let body = get_json_from(&app, "/api/points/2025?grouping=Class").await;
+        assert_eq!(field(&body["tables"], "group"), vec!["ClassX", "G2", "O4WD"]);
+        let body = get_json_from(&app, "/api/points/2025?grouping=Class&tier=Open").await;
+        assert_eq!(field(&body["tables"], "group"), vec!["O4WD"]);
+    }
+
+    #[tokio::test]
+    async fn compare_stage_results() {
+        // The sprint in malformed.json parses fine, it's there for its mix of stage results
+        let app = fixture_app_with(&[(2025, "malformed.json")]);
+        let body = get_json_from(&app, "/api/compare?event=2025|sprint&driver=10&benchmarks=20").await;
+        let stages = body["stages"].as_array().unwrap();
+
+        let results: Vec<_> = stages.iter().map(|s| s["benchmarks"][0]["kind"].clone()).collect();
+        // Nobody ran the second stage but the third was, the last one hasn't been run yet
+        assert_eq!(results, ["Time", "Cancelled", "Nominal", "NotRun"]);
+        // A Super Rally time counts towards the total but isn't compared
+        assert_eq!(stages[2]["benchmarks"][0]["delta"], serde_json::Value::Null);
+        assert_eq!(stages[2]["benchmarks"][0]["position"]["total"], "02:12.365");
+        // Cancelled stages don't count against anyone
+        assert_eq!(stages[1]["driver"]["position"]["overall"], 1);
+        assert_eq!(stages[1]["benchmarks"][0]["position"]["overall"], 2);
+    }
+
+    #[tokio::test]
+    async fn compare_retired() {
+        let body = get_json("/api/compare?event=2025|test_rally&driver=10&benchmarks=30").await;
+        let ridge = &body["stages"][1]["benchmarks"][0];
+        assert_eq!(ridge["kind"], "Retired");
+        assert_eq!(ridge["retirement"]["reason"], "mechanical");
+        assert_eq!(ridge["delta"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn compare_without_stage_history() {
+        let body = get_json("/api/compare?event=2025|test_rally&driver=10&history=true").await;
+        // SS1 Collision only shares one stage name, and not the length
+        assert_eq!(body["stage_history"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn points_for_year() {
+        let body = get_json("/api/points/2025?grouping=Class&best_of=1").await;
+        let table = &body["tables"][0];
+        assert_eq!(table["group"], "O2WD");
+        let rounds: Vec<_> = table["rounds"].as_array().unwrap().iter().map(|r| r["title"].clone()).collect();
+        assert_eq!(rounds, vec!["Test Rally", "Sheet Name Collision"]);
+        let standings = table["standings"].as_array().unwrap();
+        // Quick won test_rally, Driver was second there but won the other
+        assert_eq!(standings[0]["name"], "Ada Driver");
+        assert_eq!(standings[0]["total"], 25.0);
+        assert_eq!(standings[0]["results"][0]["dropped"], true);
+        assert_eq!(standings[1]["name"], "Cal Quick");
+        assert_eq!(standings[1]["position"], 1);
+        // Retired, so never classified
+        assert_eq!(standings.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn points_coefficients_and_categories() {
+        let body = get_json("/api/points/2025?grouping=Class&coefficient=SS1%20Collision:2").await;
+        let standings = &body["tables"][0]["standings"];
+        assert_eq!(standings[0]["name"], "Ada Driver");
+        assert_eq!(standings[0]["total"], 68.0);
+
+        // Only Regional scores, and #30 never finished
+        let body = get_json("/api/points/2025?category=Regional").await;
+        assert_eq!(body["tables"], serde_json::json!([]));
+        let body = get_json("/api/points/2025?grouping=Class&category=National&category=Regional").await;
+        assert_eq!(body["scheme"]["categories"], serde_json::json!(["National", "Regional"]));
+
+        get_error("/api/points/2025?coefficient=SS1%20Collision", StatusCode::BAD_REQUEST).await;
+        get_error("/api/points/2025?coefficient=SS1%20Collision:double", StatusCode::BAD_REQUEST).await;
+    }
+}
@@ -0,0 +1,461 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::str::FromStr;
+
+use crate::spreadsheet::SpreadSheetError;
+use crate::standings::{Standing, Standings};
+use crate::structures::{self, Delta, Precision, StageResult, StageStatus, StageTime, UidMap};
+
+/// A driver compared stage by stage against a set of benchmarks. Every output format, the
+/// workbook included, is rendered from this so they can't drift apart.
+#[derive(Serialize, Clone, Debug)]
+pub struct Comparison {
+    pub rally: String,
+    pub slug: String,
+    pub driver: Competitor,
+    pub benchmarks: Vec<Competitor>,
+    pub stages: Vec<StageComparison>,
+    /// What every time was rounded to, missing when they're as published
+    pub precision: Option<Precision>,
+    /// Other runs of this rally's stages, only filled in by add_stage_history
+    pub stage_history: Vec<StageHistory>,
+    /// Everyone the driver was up against in class, only filled in by add_class_field
+    pub class_field: Vec<FieldEntry>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Competitor {
+    pub number: usize,
+    pub uid: usize,
+    pub driver: String,
+    pub codriver: String,
+    /// Short driver/codriver surnames, for column headings
+    pub names: String,
+    pub class: structures::Class,
+    pub category: structures::Category,
+    pub model: String,
+    /// Where they stood at the end of the rally
+    pub result: Option<Standing>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct StageComparison {
+    pub name: String,
+    pub length: f32,
+    /// Distance to each split including the finish, empty for stages without splits
+    pub split_distances: Vec<f32>,
+    /// Fastest time in the driver's class
+    pub class_fastest: Option<StageTime>,
+    /// Fastest time in the driver's class and category
+    pub category_fastest: Option<StageTime>,
+    pub driver: CompetitorStage,
+    pub benchmarks: Vec<BenchmarkStage>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CompetitorStage {
+    #[serde(flatten)]
+    pub result: StageResult,
+    pub penalties: StageTime,
+    pub status: StageStatus,
+    pub super_rally: bool,
+    /// Only set on the first stage missing a time because of it
+    pub retirement: Option<structures::Retirement>,
+    pub position: Option<Standing>,
+    /// Cumulative time at each split including the finish, None where one wasn't recorded
+    pub splits: Vec<Option<StageTime>>,
+    /// Time for each sector between splits, None where either end wasn't recorded
+    pub sectors: Vec<Option<StageTime>>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BenchmarkStage {
+    #[serde(flatten)]
+    pub stage: CompetitorStage,
+    /// Seconds per mile, missing when either time can't be compared
+    pub delta: Option<Delta>,
+    pub split_deltas: Vec<SplitDelta>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SplitDelta {
+    pub sector: Option<Delta>,
+    pub cumulative: Option<Delta>,
+}
+
+/// Who counts as the driver's field
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldScope {
+    /// Everyone in the driver's class
+    Class,
+    /// Everyone in the driver's class and category
+    Category,
+}
+
+impl FromStr for FieldScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "class" => Ok(FieldScope::Class),
+            "category" => Ok(FieldScope::Category),
+            _ => Err(format!("Expected class or category, not {}", s)),
+        }
+    }
+}
+
+/// One entrant in the driver's field, the driver included
+#[derive(Serialize, Clone, Debug)]
+pub struct FieldEntry {
+    #[serde(flatten)]
+    pub competitor: Competitor,
+    /// In the same order as Comparison::stages
+    pub stages: Vec<FieldStage>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FieldStage {
+    #[serde(flatten)]
+    pub stage: CompetitorStage,
+    /// Seconds per mile against the driver, missing for the driver themselves
+    pub delta: Option<Delta>,
+}
+
+/// Every run of one of the rally's stages, this one included, found by Rally::shared_stages
+#[derive(Serialize, Clone, Debug)]
+pub struct StageHistory {
+    /// Index into Comparison::stages
+    pub stage: usize,
+    /// Oldest year first
+    pub runs: Vec<StageRun>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct StageRun {
+    pub year: usize,
+    pub rally: String,
+    pub slug: String,
+    pub stage: usize,
+    pub name: String,
+    pub length: f32,
+    /// Missing if the driver wasn't entered or has no comparable time
+    pub driver: Option<StageTime>,
+    /// In the same order as Comparison::benchmarks
+    pub benchmarks: Vec<Option<BenchmarkRun>>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BenchmarkRun {
+    /// Car numbers change, so this may not be the one they have in the rally being compared
+    pub number: usize,
+    pub time: StageTime,
+    pub delta: Option<Delta>,
+}
+
+/// Rallies need this many stages in common to count as the same event in another year, so a
+/// "Super Special" that happens to be the same length elsewhere doesn't match
+const MIN_SHARED_STAGES: usize = 2;
+
+/// A stage time that can be compared, i.e. driven rather than given under Super Rally
+fn comparable_time(entry: &structures::Entry, stage: usize, precision: Option<Precision>) -> Option<StageTime> {
+    entry.time_on(stage).map(|t| round(t, precision))
+}
+
+/// Round to the precision the comparison asked for, if it asked for one
+fn round(time: StageTime, precision: Option<Precision>) -> StageTime {
+    precision.map_or(time, |p| time.with_precision(p))
+}
+
+fn round_standing(standing: Option<&Standing>, precision: Option<Precision>) -> Option<Standing> {
+    standing.map(|s| precision.map_or_else(|| s.clone(), |p| s.with_precision(p)))
+}
+
+impl StageRun {
+    fn new(year: usize, rally: &structures::Rally, stage: usize, driver: usize, benchmarks: &[Competitor], precision: Option<Precision>) -> Self {
+        let length = rally.stages[stage].length;
+        let driver = rally.entry_by_driver_uid(driver)
+            .and_then(|e| comparable_time(e, stage, precision));
+        let benchmarks = benchmarks.iter().map(|benchmark| {
+            let entry = rally.entry_by_driver_uid(benchmark.uid)?;
+            let time = comparable_time(entry, stage, precision)?;
+            Some(BenchmarkRun {
+                number: entry.number,
+                time,
+                delta: driver.map(|d| d.diff_per_mile(&time, length)),
+            })
+        }).collect();
+
+        StageRun {
+            year,
+            rally: rally.title.clone(),
+            slug: rally.slug.clone(),
+            stage,
+            name: rally.stages[stage].name.clone(),
+            length,
+            driver,
+            benchmarks,
+        }
+    }
+}
+
+impl Competitor {
+    fn new(entry: &structures::Entry, uids: &UidMap, standings: &Standings, precision: Option<Precision>) -> Self {
+        Competitor {
+            number: entry.number,
+            uid: entry.driverUID,
+            driver: entry.driver_name(uids),
+            codriver: entry.codriver_name(uids),
+            names: entry.names(uids),
+            class: entry.class.clone(),
+            category: entry.category.clone(),
+            model: entry.model.clone(),
+            result: round_standing(standings.final_standings().and_then(|s| s.for_entry(entry.number)), precision),
+        }
+    }
+}
+
+impl CompetitorStage {
+    /// Data published part way through a stage can be short of times or splits, anything
+    /// missing is taken as not run yet rather than trusting it lines up with the stages
+    fn new(entry: &structures::Entry, stage: usize, split_count: usize, standings: &Standings, precision: Option<Precision>) -> Self {
+        let result = entry.times.get(stage).map_or(StageResult::NotRun, |r| r.map(|t| round(t, precision)));
+        let status = entry.stage_status(stage);
+        let retirement = entry.retirement_on(stage)
+            .filter(|_| status == StageStatus::Retired && result == StageResult::Retired)
+            .filter(|r| (r.stage..stage).all(|s| entry.times.get(s).and_then(|r| r.recorded()).is_some()))
+            .cloned();
+        let rounded = |times: Vec<Option<StageTime>>| times.into_iter().map(|t| t.map(|t| round(t, precision))).collect();
+        let (splits, sectors) = (rounded(entry.splits_on(stage, split_count)), rounded(entry.sectors_on(stage, split_count)));
+
+        CompetitorStage {
+            result,
+            penalties: round(entry.penalties_on(stage), precision),
+            status,
+            super_rally: entry.is_super_rally(stage),
+            retirement,
+            position: round_standing(standings.for_entry(stage, entry.number), precision),
+            splits,
+            sectors,
+        }
+    }
+
+    /// The stage time, or why there isn't one
+    pub fn display(&self) -> String {
+        self.note()
+            .or_else(|| self.result.recorded().map(|t| t.to_string()))
+            .unwrap_or_default()
+    }
+
+    /// Why there's no time, if there isn't one and we know
+    pub fn note(&self) -> Option<String> {
+        match (self.result, &self.retirement) {
+            (StageResult::Retired, Some(r)) => Some(format!("DNF {}: {}", r.control, r.reason)),
+            (StageResult::Retired, None) if self.status == StageStatus::Retired => Some("DNF".to_string()),
+            (StageResult::Cancelled, _) => Some("Cancelled".to_string()),
+            _ => None,
+        }
+    }
+
+    /// Super Rally times are notional, so there's nothing meaningful to compare
+    fn comparable(&self, other: &Self) -> bool {
+        !(self.super_rally || other.super_rally)
+    }
+
+    /// Seconds per mile from this stage to `other`, if both have a time that can be compared
+    fn delta(&self, other: &Self, length: f32) -> Option<Delta> {
+        self.result.diff_per_mile(&other.result, length)
+    }
+}
+
+impl Comparison {
+    /// Times are rounded to `precision` before anything is worked out from them, so deltas
+    /// match the times shown. Positions stay as the full precision times put them.
+    pub fn build(rally: &structures::Rally, uids: &UidMap, driver: usize, benchmarks: &[usize], precision: Option<Precision>) -> Result<Self, Box<dyn Error>> {
+        let driver = rally.entry_by_driver_number(driver)
+            .ok_or_else(|| Box::new(SpreadSheetError::new(format!("Driver {} did not race in {}", driver, rally.title))))?;
+        let benchmarks: Vec<_> = rally.entries.iter().filter(|x| benchmarks.contains(&x.number)).collect();
+
+        let standings = Standings::compute(rally);
+
+        let mut stages = vec![];
+        for (stage_number, stage) in rally.stages.iter().enumerate() {
+            let class_fastest = rally.entries.iter()
+                .filter(|x| x.class == driver.class)
+                .filter_map(|x| comparable_time(x, stage_number, precision))
+                .min();
+
+            let category_fastest = rally.entries.iter()
+                .filter(|x| x.class == driver.class)
+                .filter(|x| x.category == driver.category)
+                .filter_map(|x| comparable_time(x, stage_number, precision))
+                .min();
+
+            let split_distances = if stage.has_splits() {
+                stage.splits_with_finish()
+            } else {
+                vec![]
+            };
+
+            let driver_stage = CompetitorStage::new(driver, stage_number, split_distances.len(), &standings, precision);
+            let benchmark_stages = benchmarks.iter().map(|benchmark| {
+                let benchmark_stage = CompetitorStage::new(benchmark, stage_number, split_distances.len(), &standings, precision);
+                let comparable = driver_stage.comparable(&benchmark_stage);
+
+                let delta = driver_stage.delta(&benchmark_stage, stage.length);
+
+                let mut split_deltas = vec![];
+                let mut prev_split_distance = 0.0;
+                for (n, split_distance) in split_distances.iter().enumerate() {
+                    let pair = |a: &[Option<StageTime>], b: &[Option<StageTime>]| match (a.get(n), b.get(n)) {
+                        (Some(Some(a)), Some(Some(b))) if comparable => Some((*a, *b)),
+                        _ => None,
+                    };
+                    let this_sector = split_distance - prev_split_distance;
+                    split_deltas.push(SplitDelta {
+                        sector: pair(&driver_stage.sectors, &benchmark_stage.sectors)
+                            .map(|(d, b)| d.diff_per_mile(&b, this_sector)),
+                        cumulative: pair(&driver_stage.splits, &benchmark_stage.splits)
+                            .map(|(d, b)| d.diff_per_mile(&b, *split_distance)),
+                    });
+                    prev_split_distance = *split_distance;
+                }
+
+                BenchmarkStage {
+                    stage: benchmark_stage,
+                    delta,
+                    split_deltas,
+                }
+            }).collect();
+
+            stages.push(StageComparison {
+                name: stage.name.clone(),
+                length: stage.length,
+                split_distances,
+                class_fastest,
+                category_fastest,
+                driver: driver_stage,
+                benchmarks: benchmark_stages,
+            });
+        }
+
+        Ok(Comparison {
+            rally: rally.title.clone(),
+            slug: rally.slug.clone(),
+            driver: Competitor::new(driver, uids, &standings, precision),
+            benchmarks: benchmarks.iter().map(|b| Competitor::new(b, uids, &standings, precision)).collect(),
+            stages,
+            precision,
+            stage_history: vec![],
+            class_field: vec![],
+        })
+    }
+
+    /// What the times should be shown to: whatever was asked for, or else the finest any of the
+    /// driver's or benchmarks' times were published to
+    pub fn time_precision(&self) -> Precision {
+        self.precision.unwrap_or_else(|| {
+            self.stages.iter()
+                .flat_map(|s| std::iter::once(&s.driver).chain(s.benchmarks.iter().map(|b| &b.stage)))
+                .filter_map(|s| s.result.recorded())
+                .map(|t| t.precision())
+                .max()
+                .unwrap_or_default()
+        })
+    }
+
+    /// Fill in everyone in the driver's class, or class and category, from `rally`, the one this
+    /// comparison was built from. Entrants are in the order they finished, then anyone who
+    /// didn't.
+    pub fn add_class_field(&mut self, rally: &structures::Rally, uids: &UidMap, scope: FieldScope) {
+        let standings = Standings::compute(rally);
+        let (class, category) = (&self.driver.class, &self.driver.category);
+        let mut entries: Vec<_> = rally.entries.iter()
+            .filter(|e| e.class == *class)
+            .filter(|e| scope == FieldScope::Class || e.category == *category)
+            .collect();
+        if let Some(last) = standings.final_standings() {
+            entries.sort_by_key(|e| last.standings.iter().position(|s| s.number == e.number).unwrap_or(usize::MAX));
+        }
+
+        self.class_field = entries.into_iter().map(|entry| {
+            let stages = self.stages.iter().enumerate().map(|(stage_number, stage)| {
+                let field_stage = CompetitorStage::new(entry, stage_number, 0, &standings, self.precision);
+                FieldStage {
+                    delta: (entry.number != self.driver.number)
+                        .then(|| stage.driver.delta(&field_stage, stage.length))
+                        .flatten(),
+                    stage: field_stage,
+                }
+            }).collect();
+            FieldEntry {
+                competitor: Competitor::new(entry, uids, &standings, self.precision),
+                stages,
+            }
+        }).collect();
+    }
+
+    /// Look for the stages of `rally`, the one this comparison was built from, in `others` so
+    /// times can be compared across years. The driver and benchmarks are matched by uid since
+    /// car numbers change. Only stages found somewhere else get a history.
+    pub fn add_stage_history(&mut self, year: usize, rally: &structures::Rally, others: &[(usize, &structures::Rally)]) {
+        let mut others: Vec<_> = others.iter()
+            .filter(|(y, other)| !(*y == year && other.slug == rally.slug))
+            .map(|(y, other)| (*y, *other, rally.shared_stages(other)))
+            .filter(|(_, _, shared)| {
+                let mut ours: Vec<_> = shared.iter().map(|(i, _)| *i).collect();
+                ours.dedup();
+                ours.len() >= MIN_SHARED_STAGES
+            })
+            .collect();
+        others.sort_by_key(|(y, _, _)| *y);
+
+        self.stage_history = (0..rally.stages.len()).filter_map(|stage| {
+            let mut runs = vec![];
+            for (other_year, other, shared) in &others {
+                for (_, theirs) in shared.iter().filter(|(ours, _)| *ours == stage) {
+                    runs.push(StageRun::new(*other_year, other, *theirs, self.driver.uid, &self.benchmarks, self.precision));
+                }
+            }
+            if runs.is_empty() {
+                return None;
+            }
+            runs.push(StageRun::new(year, rally, stage, self.driver.uid, &self.benchmarks, self.precision));
+            runs.sort_by_key(|r| r.year);
+            Some(StageHistory { stage, runs })
+        }).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entry, rally, stage, uids};
+
+    #[test]
+    fn short_data() {
+        let mut split = stage("Split", 4.0);
+        split["splits"] = serde_json::json!([2.0]);
+        // Part way through the second stage, the driver's time and splits aren't out yet
+        let mut benchmark = entry(20, 3, "O2WD", "National", &["5:00.0", "4:00.0"]);
+        benchmark["splits"] = serde_json::json!([[], ["2:00.0"]]);
+        let rally = rally("short", vec![stage("Forest", 5.0), split], vec![
+            entry(10, 1, "O2WD", "National", &["5:10.0"]),
+            benchmark,
+            entry(30, 5, "O2WD", "National", &["5:20.0", "4:10.0"]),
+        ]);
+
+        let comparison = Comparison::build(&rally, &uids(6), 10, &[20, 30], None).unwrap();
+        let stage = &comparison.stages[1];
+        assert_eq!(stage.driver.result, StageResult::NotRun);
+        assert_eq!(stage.driver.splits, vec![None, None]);
+        let time = |t| serde_json::from_value(serde_json::json!(t)).unwrap();
+        assert_eq!(stage.benchmarks[0].stage.splits, vec![Some(time("2:00.0")), Some(time("4:00.0"))]);
+        assert!(stage.benchmarks[0].delta.is_none());
+        assert!(stage.benchmarks[0].split_deltas.iter().all(|d| d.sector.is_none() && d.cumulative.is_none()));
+        // No splits at all still puts the finish at the finish
+        assert_eq!(stage.benchmarks[1].stage.splits, vec![None, Some(time("4:10.0"))]);
+        assert_eq!(stage.benchmarks[1].stage.sectors, vec![None, None]);
+        crate::spreadsheet::build_workbook(&comparison, Default::default()).unwrap();
+    }
+}
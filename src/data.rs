@@ -0,0 +1,77 @@
+//! Which data files there are and loading them, so everything that serves or checks them agrees
+use std::path::Path;
+
+use crate::error::DataError;
+use crate::spreadsheet;
+use crate::structures::{Aliases, Rally, Uid, UidMap};
+
+/// Every year we have results for, in the order they're displayed in the web interface
+pub const RALLY_DATA: &[(usize, &str)] = &[
+    (2026, "2026rallies.json"),
+    (2025, "2025rallies.json"),
+    (2024, "2024rallies.json"),
+];
+
+pub const UIDS_FILE: &str = "uidsSmall.json";
+
+/// Ours rather than upstream's, so it's never fetched
+pub const ALIASES_FILE: &str = "aliases.json";
+
+/// Whatever could be loaded from a set of data files
+pub struct Loaded {
+    pub uids: UidMap,
+    /// In the same order as the files were given
+    pub rallies: Vec<(usize, Vec<Rally>)>,
+    /// Rally files or rallies that couldn't be loaded, by year
+    pub errors: Vec<(usize, DataError)>,
+}
+
+/// No file means no aliases, and everything is kept as published
+pub fn load_aliases(name: &str) -> Result<Aliases, DataError> {
+    if !Path::new(name).exists() {
+        return Ok(Aliases::default());
+    }
+    spreadsheet::load_sneakattack_json(name)
+}
+
+fn uid_map(uids_list: Vec<Uid>) -> UidMap {
+    uids_list.into_iter().map(|uid| (uid.uid, uid)).collect()
+}
+
+/// Nothing works without the uids, but a rally file or a rally in one that won't load is
+/// reported in errors and left out rather than stopping everything else loading
+pub fn load(uids_file: &str, rally_data: &[(usize, &str)], aliases: &Aliases) -> Result<Loaded, DataError> {
+    let uids = uid_map(spreadsheet::load_sneakattack_json(uids_file)?);
+    let mut rallies = vec![];
+    let mut errors = vec![];
+    for (year, data_file) in rally_data {
+        match spreadsheet::load_rallies(data_file) {
+            Ok(mut file) => {
+                file.rallies.iter_mut().for_each(|rally| aliases.apply(rally));
+                rallies.push((*year, file.rallies));
+                errors.extend(file.errors.into_iter().map(|e| (*year, e)));
+            },
+            Err(e) => {
+                rallies.push((*year, vec![]));
+                errors.push((*year, e));
+            },
+        }
+    }
+    Ok(Loaded { uids, rallies, errors })
+}
+
+/// Fetch every data file from `base`. Unlike load, a whole file failing fails everything, so
+/// a flaky upstream can't leave us with a year missing, though individual rallies that don't
+/// load are still only reported in errors.
+pub async fn fetch(base: &str, uids_file: &str, rally_data: &[(usize, &str)], aliases: &Aliases) -> Result<Loaded, DataError> {
+    let uids = uid_map(spreadsheet::fetch_sneakattack_json(base, uids_file).await?);
+    let mut rallies = vec![];
+    let mut errors = vec![];
+    for (year, data_file) in rally_data {
+        let mut file = spreadsheet::fetch_rallies(base, data_file).await?;
+        file.rallies.iter_mut().for_each(|rally| aliases.apply(rally));
+        rallies.push((*year, file.rallies));
+        errors.extend(file.errors.into_iter().map(|e| (*year, e)));
+    }
+    Ok(Loaded { uids, rallies, errors })
+}
@@ -0,0 +1,181 @@
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+
+/// Where in the data something went wrong, as far as it could be worked out
+#[derive(Debug, Clone, Default)]
+pub struct Location {
+    pub file: String,
+    /// Rally slug
+    pub rally: Option<String>,
+    /// Car number
+    pub entry: Option<usize>,
+    /// Stage index
+    pub stage: Option<usize>,
+}
+
+impl Location {
+    /// Follow `path` down from `value`, the whole of `file`, picking up the rally, entry and
+    /// stage it passes through
+    fn find(file: &str, value: &Value, path: &serde_path_to_error::Path) -> Self {
+        use serde_path_to_error::Segment;
+
+        let mut location = Location { file: file.to_string(), ..Location::default() };
+        location.rally = value.get("slug").and_then(|s| s.as_str()).map(String::from);
+        let mut current = Some(value);
+        let mut key: Option<&str> = None;
+        for segment in path.iter() {
+            current = match (segment, current) {
+                (Segment::Seq { index }, Some(Value::Array(items))) => {
+                    let item = items.get(*index);
+                    match key {
+                        Some("entries") => location.entry = item.and_then(|e| e["number"].as_u64()).map(|n| n as usize),
+                        Some("times" | "colors" | "splits" | "stages") if location.stage.is_none() => location.stage = Some(*index),
+                        Some("penalties" | "retirements") => location.stage = item.and_then(|p| p["stage"].as_u64()).map(|s| s as usize),
+                        _ => (),
+                    }
+                    key = None;
+                    item
+                },
+                (Segment::Map { key: next }, Some(Value::Object(fields))) => {
+                    key = Some(next.as_str());
+                    fields.get(next)
+                },
+                _ => None,
+            };
+            if let Some(slug) = current.and_then(|v| v.get("slug")).and_then(|s| s.as_str()) {
+                location.rally = Some(slug.to_string());
+            }
+        }
+        location
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(rally) = &self.rally {
+            write!(f, ": {}", rally)?;
+        }
+        if let Some(entry) = self.entry {
+            write!(f, " #{}", entry)?;
+        }
+        if let Some(stage) = self.stage {
+            write!(f, " SS{}", stage + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Problems reading the upstream data files
+#[derive(Debug)]
+pub enum DataError {
+    /// The file couldn't be read or fetched at all
+    Read { file: String, reason: String },
+    /// The file isn't JSON
+    Syntax { file: String, source: serde_json::Error },
+    /// The file is JSON, but something in it isn't what we expect
+    Invalid { location: Box<Location>, path: String, reason: String },
+}
+
+impl DataError {
+    pub fn read(file: &str, reason: impl fmt::Display) -> Self {
+        DataError::Read { file: file.to_string(), reason: reason.to_string() }
+    }
+
+    /// `value` is the whole of what was being deserialized when `error` happened
+    pub(crate) fn invalid(file: &str, value: &Value, error: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        DataError::Invalid {
+            location: Box::new(Location::find(file, value, error.path())),
+            path: error.path().to_string(),
+            reason: error.into_inner().to_string(),
+        }
+    }
+
+    /// Slug of the rally the problem is in, if it's in one we could identify
+    pub fn rally(&self) -> Option<&str> {
+        match self {
+            DataError::Invalid { location, .. } => location.rally.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataError::Read { file, reason } => write!(f, "{}: {}", file, reason),
+            DataError::Syntax { file, source } => write!(f, "{}: {}", file, source),
+            DataError::Invalid { location, path, reason } => write!(f, "{}: {} (at {})", location, reason, path),
+        }
+    }
+}
+
+impl Error for DataError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DataError::Syntax { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::spreadsheet::parse_rallies;
+    use crate::testing::{entry, rally_data, stage};
+
+    /// Whatever's wrong with #20, in the second of two rallies in the file
+    fn load_broken(broken: Value) -> Vec<String> {
+        let rally = |slug: &str, entries| rally_data(slug, vec![stage("One", 1.0), stage("Two", 1.0), stage("Three", 1.0)], entries);
+        let fine = entry(10, 0, "O4WD", "National", &["01:00.0", "01:00.0", "01:00.0"]);
+        let data = json!([rally("fine", vec![fine.clone()]), rally("broken", vec![fine, broken])]);
+        let file = parse_rallies("rallies.json", &data.to_string()).unwrap();
+        assert_eq!(file.rallies.len(), 1);
+        assert_eq!(file.rallies[0].slug, "fine");
+        assert!(file.errors.iter().all(|e| e.rally() == Some("broken")));
+        file.errors.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn stage_time_location() {
+        let broken = entry(20, 2, "O4WD", "National", &["01:00.0", "01:00.0", "01:1x.0"]);
+        assert_eq!(load_broken(broken), vec![
+            "rallies.json: broken #20 SS3: invalid stage time \"01:1x.0\" (at entries[1].times[2])",
+        ]);
+    }
+
+    #[test]
+    fn penalty_location() {
+        let mut broken = entry(20, 2, "O4WD", "National", &["01:00.0", "01:00.0", "01:00.0"]);
+        broken["penalties"] = json!([
+            {"control": "TC1", "stage": 0, "time": "10.0", "reason": "Late"},
+            {"control": "TC2", "stage": 1, "time": "1O.0", "reason": "Late"},
+        ]);
+        assert_eq!(load_broken(broken), vec![
+            "rallies.json: broken #20 SS2: invalid stage time \"1O.0\" (at entries[1].penalties[1].time)",
+        ]);
+    }
+
+    #[test]
+    fn split_location() {
+        let mut broken = entry(20, 2, "O4WD", "National", &["01:00.0", "01:00.0", "01:00.0"]);
+        broken["splits"] = json!([[], ["00:30.0", "00:4O.0"], []]);
+        assert_eq!(load_broken(broken), vec![
+            "rallies.json: broken #20 SS2: invalid stage time \"00:4O.0\" (at entries[1].splits[1][1])",
+        ]);
+    }
+
+    #[test]
+    fn file_level_problems() {
+        let error = parse_rallies("rallies.json", "[{").err().unwrap();
+        assert!(error.to_string().starts_with("rallies.json: EOF"), "{error}");
+        assert_eq!(error.rally(), None);
+
+        let error = parse_rallies("rallies.json", "{}").err().unwrap();
+        assert!(error.to_string().starts_with("rallies.json: invalid type: map"), "{error}");
+        assert_eq!(error.rally(), None);
+    }
+}
@@ -0,0 +1,283 @@
+use serde::Deserialize;
+use std::error::Error;
+use std::io::{Cursor, Write};
+
+use crate::comparison::Comparison;
+use crate::structures::StageTime;
+use crate::spreadsheet::{self, prepare_slug, split_sheet_name, WorkbookOptions, CLASS_FIELD_SHEET, STAGE_HISTORY_SHEET};
+
+/// The ways a comparison can be downloaded
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Xlsx,
+    /// One csv per sheet of the workbook, zipped together
+    Csv,
+    Json,
+}
+
+impl Format {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            Format::Csv => "application/zip",
+            Format::Json => "application/json",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Xlsx => "xlsx",
+            Format::Csv => "zip",
+            Format::Json => "json",
+        }
+    }
+}
+
+/// Options only apply to the workbook, the other formats are plain values
+pub fn render(comparison: &Comparison, format: Format, options: WorkbookOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+    match format {
+        Format::Xlsx => Ok(spreadsheet::build_workbook(comparison, options)?.save_to_buffer()?),
+        Format::Csv => csv_zip(comparison),
+        Format::Json => Ok(serde_json::to_vec(comparison)?),
+    }
+}
+
+type Table = Vec<Vec<String>>;
+
+fn overview_table(comparison: &Comparison) -> Table {
+    let mut title = vec![comparison.rally.clone(), String::new(), String::new(), String::new(), String::new()];
+    let mut headings = vec!["Stage Name".to_string(), "Length".to_string(), comparison.driver.number.to_string(), "Penalties".to_string(), "Pos (Class)".to_string()];
+    for benchmark in &comparison.benchmarks {
+        title.extend([benchmark.names.clone(), String::new()]);
+        headings.extend([benchmark.number.to_string(), "Diff s/mi".to_string()]);
+    }
+    let mut table = vec![title, headings];
+
+    for stage in &comparison.stages {
+        let driver = &stage.driver;
+        let position = driver.position.as_ref().and_then(spreadsheet::position_text).unwrap_or_default();
+        let penalties = if driver.penalties > StageTime::zero() { driver.penalties.to_string() } else { String::new() };
+        let mut row = vec![stage.name.clone(), format!("{:.2}", stage.length), driver.display(), penalties, position];
+        for benchmark in &stage.benchmarks {
+            row.push(benchmark.stage.display());
+            row.push(benchmark.delta.map(|d| d.to_string()).unwrap_or_default());
+        }
+        table.push(row);
+    }
+
+    let competitors: Vec<_> = std::iter::once(&comparison.driver).chain(comparison.benchmarks.iter()).collect();
+    for label in ["Stage Time", "Penalties", "Total"] {
+        let mut row = vec![label.to_string(), String::new()];
+        for (i, competitor) in competitors.iter().enumerate() {
+            let value = competitor.result.as_ref().map(|s| match label {
+                "Stage Time" => s.unpenalised,
+                "Penalties" => s.penalties,
+                _ => s.total,
+            });
+            row.push(value.map(|v| v.to_string()).unwrap_or_default());
+            // The driver has penalty and position columns, benchmarks a delta column
+            let padding = if i == 0 { 2 } else { 1 };
+            row.extend(std::iter::repeat_n(String::new(), padding));
+        }
+        table.push(row);
+    }
+
+    table
+}
+
+fn splits_table(comparison: &Comparison, stage_index: usize) -> Table {
+    let stage = &comparison.stages[stage_index];
+    let mut headings = vec!["Team".to_string(), String::new(), String::new()];
+    for split in &stage.split_distances {
+        headings.extend([format!("{:.2}", split), "Diff s/mi".to_string(), "Cumulative s/mi".to_string()]);
+    }
+    let mut table = vec![
+        vec![stage.name.clone(), "Length".to_string(), format!("{:.2}", stage.length)],
+        headings,
+    ];
+
+    let mut driver_row = vec![comparison.driver.names.clone(), String::new(), String::new()];
+    for split in &stage.driver.splits {
+        driver_row.extend([split.map(|s| s.to_string()).unwrap_or_default(), String::new(), String::new()]);
+    }
+    table.push(driver_row);
+
+    for (benchmark, bm_stage) in comparison.benchmarks.iter().zip(stage.benchmarks.iter()) {
+        let mut row = vec![benchmark.names.clone(), String::new(), String::new()];
+        for (split, delta) in bm_stage.stage.splits.iter().zip(bm_stage.split_deltas.iter()) {
+            row.push(split.map(|s| s.to_string()).unwrap_or_default());
+            row.push(delta.sector.map(|d| d.to_string()).unwrap_or_default());
+            row.push(delta.cumulative.map(|d| d.to_string()).unwrap_or_default());
+        }
+        table.push(row);
+    }
+
+    table
+}
+
+fn stage_history_table(comparison: &Comparison) -> Table {
+    let mut table = vec![vec![comparison.rally.clone()]];
+    for history in &comparison.stage_history {
+        let stage = &comparison.stages[history.stage];
+        table.push(vec![format!("SS{} {}", history.stage + 1, stage.name)]);
+
+        let mut headings = vec!["Year".to_string(), "Rally".to_string(), "Stage".to_string(), "Length".to_string(), comparison.driver.names.clone()];
+        for benchmark in &comparison.benchmarks {
+            headings.extend([benchmark.names.clone(), "Diff s/mi".to_string()]);
+        }
+        table.push(headings);
+
+        for run in &history.runs {
+            let mut row = vec![
+                run.year.to_string(),
+                run.rally.clone(),
+                format!("SS{} {}", run.stage + 1, run.name),
+                format!("{:.2}", run.length),
+                run.driver.map(|t| t.to_string()).unwrap_or_default(),
+            ];
+            for benchmark in &run.benchmarks {
+                row.push(benchmark.as_ref().map(|b| b.time.to_string()).unwrap_or_default());
+                row.push(benchmark.as_ref().and_then(|b| b.delta).map(|d| d.to_string()).unwrap_or_default());
+            }
+            table.push(row);
+        }
+        table.push(vec![]);
+    }
+    table
+}
+
+fn class_field_table(comparison: &Comparison) -> Table {
+    let mut title = vec![comparison.rally.clone(), String::new()];
+    let mut headings = vec!["Car".to_string(), "Team".to_string()];
+    for (stage_number, stage) in comparison.stages.iter().enumerate() {
+        title.extend([format!("SS{} {}", stage_number + 1, stage.name), String::new(), String::new(), String::new()]);
+        headings.extend(["Time", "Total", "Pos (Class)", "Diff s/mi"].map(String::from));
+    }
+    let mut table = vec![title, headings];
+
+    for entry in &comparison.class_field {
+        let mut row = vec![entry.competitor.number.to_string(), entry.competitor.names.clone()];
+        for stage in &entry.stages {
+            let position = stage.stage.position.as_ref();
+            row.push(stage.stage.display());
+            row.push(position.filter(|p| p.is_classified()).map(|p| p.total.to_string()).unwrap_or_default());
+            row.push(position.and_then(spreadsheet::position_text).unwrap_or_default());
+            row.push(stage.delta.map(|d| d.to_string()).unwrap_or_default());
+        }
+        table.push(row);
+    }
+    table
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn to_csv(table: &Table) -> String {
+    let mut out = String::new();
+    for row in table {
+        let fields: Vec<_> = row.iter().map(|f| csv_field(f)).collect();
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+fn csv_zip(comparison: &Comparison) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut sheets = vec![(prepare_slug(&comparison.slug), overview_table(comparison))];
+    for (stage_number, stage) in comparison.stages.iter().enumerate() {
+        if stage.split_distances.is_empty() {
+            continue
+        }
+        sheets.push((split_sheet_name(stage_number, stage), splits_table(comparison, stage_number)));
+    }
+    if !comparison.class_field.is_empty() {
+        sheets.push((CLASS_FIELD_SHEET.to_string(), class_field_table(comparison)));
+    }
+    if !comparison.stage_history.is_empty() {
+        sheets.push((STAGE_HISTORY_SHEET.to_string(), stage_history_table(comparison)));
+    }
+
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default();
+    for (name, table) in sheets {
+        zip.start_file(format!("{}.csv", name), options)?;
+        zip.write_all(to_csv(&table).as_bytes())?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use crate::testing::{entry, rally, stage, uids};
+
+    fn comparison() -> Comparison {
+        let mut benchmark = entry(20, 3, "O2WD", "National", &["4:50.0", "2:55.0"]);
+        benchmark["penalties"] = serde_json::json!([
+            {"control": "TC2", "stage": 1, "time": "10.0", "reason": "1 minute late"},
+        ]);
+        let mut split = stage("Ridge", 3.0);
+        split["splits"] = serde_json::json!([1.5]);
+        let mut driver = entry(10, 1, "O2WD", "National", &["5:00.0", "3:00.0"]);
+        driver["splits"] = serde_json::json!([[], ["1:30.0"]]);
+        let rally = rally("Hills, \"Wet\"", vec![stage("Forest", 5.0), split], vec![driver, benchmark]);
+        Comparison::build(&rally, &uids(4), 10, &[20], None).unwrap()
+    }
+
+    #[test]
+    fn csv_quoting() {
+        assert_eq!(csv_field("Forest"), "Forest");
+        assert_eq!(csv_field("Smith, J"), r#""Smith, J""#);
+        assert_eq!(csv_field(r#"The "Flying" One"#), r#""The ""Flying"" One""#);
+        assert_eq!(csv_field("Two\nLines"), "\"Two\nLines\"");
+        let table = vec![vec!["a".to_string(), "b,c".to_string()], vec![]];
+        assert_eq!(to_csv(&table), "a,\"b,c\"\r\n\r\n");
+    }
+
+    #[test]
+    fn overview_csv() {
+        let table = overview_table(&comparison());
+        let row = |cells: &[&str]| cells.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(table, vec![
+            row(&["Hills, \"Wet\"", "", "", "", "", "3/4", ""]),
+            row(&["Stage Name", "Length", "10", "Penalties", "Pos (Class)", "20", "Diff s/mi"]),
+            row(&["Forest", "5.00", "05:00.0", "", "2 (2)", "04:50.0", "-2.00"]),
+            // Still second after the benchmark's penalty
+            row(&["Ridge", "3.00", "03:00.0", "", "2 (2)", "02:55.0", "-1.67"]),
+            row(&["Stage Time", "", "08:00.0", "", "", "07:45.0", ""]),
+            row(&["Penalties", "", "00", "", "", "10.0", ""]),
+            row(&["Total", "", "08:00.0", "", "", "07:55.0", ""]),
+        ]);
+    }
+
+    #[test]
+    fn csv_sheets() {
+        let comparison = comparison();
+        let buf = render(&comparison, Format::Csv, WorkbookOptions::default()).unwrap();
+        let mut zip = zip::ZipArchive::new(Cursor::new(buf)).unwrap();
+        let names: Vec<_> = zip.file_names().map(String::from).collect();
+        assert_eq!(names.len(), 2);
+
+        let mut overview = String::new();
+        zip.by_name(&format!("{}.csv", prepare_slug(&comparison.slug))).unwrap().read_to_string(&mut overview).unwrap();
+        assert!(overview.starts_with("\"Hills, \"\"Wet\"\"\",,,,,3/4,\r\nStage Name,Length,10,"));
+
+        let mut splits = String::new();
+        zip.by_name("SS2 Ridge.csv").unwrap().read_to_string(&mut splits).unwrap();
+        assert_eq!(splits.lines().collect::<Vec<_>>(), vec![
+            "Ridge,Length,3.00",
+            "Team,,,1.50,Diff s/mi,Cumulative s/mi,3.00,Diff s/mi,Cumulative s/mi",
+            "1/2,,,01:30.0,,,03:00.0,,",
+            // No splits from the benchmark, so only the finish can be compared
+            "3/4,,,,,,02:55.0,,-1.67",
+        ]);
+    }
+}
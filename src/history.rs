@@ -0,0 +1,117 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::standings::{Standing, Standings};
+use crate::structures;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum Role {
+    Driver,
+    Codriver,
+}
+
+/// One entry someone had, from either seat
+#[derive(Serialize, Clone, Debug)]
+pub struct Appearance {
+    pub year: usize,
+    pub slug: String,
+    pub rally: String,
+    pub role: Role,
+    /// Whoever was in the other seat
+    pub partner: usize,
+    pub number: usize,
+    pub class: structures::Class,
+    pub category: structures::Category,
+    pub model: String,
+    /// Where they stood at the end of the rally
+    pub result: Option<Standing>,
+}
+
+/// Every entry each uid has had, as driver or codriver, across all the rallies it was built from
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct History {
+    appearances: HashMap<usize, Vec<Appearance>>,
+}
+
+impl History {
+    /// Appearances are kept oldest year first, then in the order the rallies were given
+    pub fn build<'a>(rallies: impl IntoIterator<Item = (usize, &'a structures::Rally)>) -> Self {
+        let mut rallies: Vec<_> = rallies.into_iter().collect();
+        rallies.sort_by_key(|(year, _)| *year);
+
+        let mut appearances: HashMap<usize, Vec<Appearance>> = HashMap::new();
+        for (year, rally) in rallies {
+            let standings = Standings::compute(rally);
+            for entry in &rally.entries {
+                let seats = [
+                    (entry.driverUID, Role::Driver, entry.codriverUID),
+                    (entry.codriverUID, Role::Codriver, entry.driverUID),
+                ];
+                for (uid, role, partner) in seats {
+                    appearances.entry(uid).or_default().push(Appearance {
+                        year,
+                        slug: rally.slug.clone(),
+                        rally: rally.title.clone(),
+                        role,
+                        partner,
+                        number: entry.number,
+                        class: entry.class.clone(),
+                        category: entry.category.clone(),
+                        model: entry.model.clone(),
+                        result: standings.final_standings()
+                            .and_then(|s| s.for_entry(entry.number))
+                            .cloned(),
+                    });
+                }
+            }
+        }
+
+        History {
+            appearances,
+        }
+    }
+
+    pub fn for_uid(&self, uid: usize) -> &[Appearance] {
+        self.appearances.get(&uid).map_or(&[], |a| a.as_slice())
+    }
+
+    /// Everyone with at least one appearance
+    pub fn uids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.appearances.keys().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entry, rally, stage};
+
+    #[test]
+    fn oldest_year_first() {
+        let first = rally("spring", vec![stage("Forest", 5.0)], vec![
+            entry(10, 1, "O2WD", "National", &["5:00.0"]),
+            entry(20, 3, "O2WD", "National", &["4:50.0"]),
+        ]);
+        let second = rally("autumn", vec![stage("Forest", 5.0)], vec![
+            entry(11, 3, "O2WD", "National", &["5:00.0"]),
+        ]);
+        let earlier = rally("last_year", vec![stage("Forest", 5.0)], vec![
+            entry(7, 2, "L2WD", "National", &["5:30.0"]),
+        ]);
+        let history = History::build([(2025, &first), (2024, &earlier), (2025, &second)]);
+
+        // Within a year they stay in the order they were given
+        let rallies = |uid| history.for_uid(uid).iter().map(|a| (a.year, a.slug.as_str())).collect::<Vec<_>>();
+        assert_eq!(rallies(3), vec![(2024, "last_year"), (2025, "spring"), (2025, "autumn")]);
+        assert_eq!(rallies(2), vec![(2024, "last_year"), (2025, "spring")]);
+
+        let seats: Vec<_> = history.for_uid(3).iter().map(|a| (a.role, a.partner, a.number)).collect();
+        assert_eq!(seats, vec![(Role::Codriver, 2, 7), (Role::Driver, 4, 20), (Role::Driver, 4, 11)]);
+        assert_eq!(history.for_uid(3)[1].result.as_ref().and_then(|r| r.overall), Some(1));
+
+        assert!(history.for_uid(99).is_empty());
+        let mut uids: Vec<_> = history.uids().collect();
+        uids.sort();
+        assert_eq!(uids, vec![1, 2, 3, 4]);
+    }
+}
@@ -1,2 +1,14 @@
+pub mod comparison;
+pub mod data;
+pub mod error;
+pub mod export;
+pub mod history;
+pub mod points;
+pub mod season;
 pub mod spreadsheet;
+pub mod standings;
 pub mod structures;
+pub mod suggest;
+#[cfg(test)]
+mod testing;
+pub mod validate;
@@ -0,0 +1,322 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::standings::Standings;
+use crate::structures::{self, UidMap};
+
+/// How entries are split up into separate championships
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum Grouping {
+    Overall,
+    Class,
+    Category,
+    ClassAndCategory,
+}
+
+/// How championship points are awarded. Loads from JSON, anything left out takes the default.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct PointsScheme {
+    /// Points for first, second and so on within a group, positions past the end score nothing
+    pub positions: Vec<f32>,
+    pub grouping: Grouping,
+    /// Only count each driver's best results, dropping the rest
+    pub best_of: Option<usize>,
+    /// Multiplier for particular rallies by slug, e.g. 2.0 for a double points finale
+    pub coefficients: HashMap<String, f32>,
+    /// Categories that score at all, everyone does if this is missing
+    pub categories: Option<Vec<structures::Category>>,
+    /// Only classes with this drivetrain score, classes that don't say never do
+    pub drivetrain: Option<structures::Drivetrain>,
+    /// Only open or only limited classes score, classes that are neither never do
+    pub tier: Option<structures::Tier>,
+}
+
+impl Default for PointsScheme {
+    fn default() -> Self {
+        PointsScheme {
+            positions: vec![25.0, 18.0, 15.0, 12.0, 10.0, 8.0, 6.0, 4.0, 2.0, 1.0],
+            grouping: Grouping::ClassAndCategory,
+            best_of: None,
+            coefficients: HashMap::new(),
+            categories: None,
+            drivetrain: None,
+            tier: None,
+        }
+    }
+}
+
+impl PointsScheme {
+    fn group(&self, entry: &structures::Entry) -> String {
+        match self.grouping {
+            Grouping::Overall => "Overall".to_string(),
+            Grouping::Class => entry.class.to_string(),
+            Grouping::Category => entry.category.to_string(),
+            Grouping::ClassAndCategory => format!("{} {}", entry.category, entry.class),
+        }
+    }
+
+    fn scores(&self, entry: &structures::Entry) -> bool {
+        self.categories.as_ref().is_none_or(|c| c.contains(&entry.category))
+            && self.drivetrain.is_none_or(|d| entry.class.drivetrain() == Some(d))
+            && self.tier.is_none_or(|t| entry.class.tier() == Some(t))
+    }
+
+    fn points(&self, rally: &structures::Rally, position: usize) -> f32 {
+        let coefficient = self.coefficients.get(&rally.slug).copied().unwrap_or(1.0);
+        self.positions.get(position - 1).copied().unwrap_or(0.0) * coefficient
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Championship {
+    pub scheme: PointsScheme,
+    /// One table per group, in name order
+    pub tables: Vec<ChampionshipTable>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ChampionshipTable {
+    pub group: String,
+    /// The rallies anyone in the group finished, in the order they were given
+    pub rounds: Vec<Round>,
+    pub standings: Vec<ChampionshipStanding>,
+}
+
+/// Rallies are told apart by slug, titles can repeat within a season
+#[derive(Serialize, Clone, Debug)]
+pub struct Round {
+    pub slug: String,
+    pub title: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ChampionshipStanding {
+    /// Shared by drivers on the same points
+    pub position: usize,
+    pub uid: usize,
+    pub name: String,
+    pub total: f32,
+    /// One per round, missing where they didn't finish
+    pub results: Vec<Option<RoundResult>>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RoundResult {
+    /// Finishing position within the group
+    pub position: usize,
+    pub points: f32,
+    /// Outside their best_of results so not counted in the total
+    pub dropped: bool,
+}
+
+/// Results so far for one group while the championship is being worked out
+#[derive(Default)]
+struct GroupResults {
+    rounds: Vec<Round>,
+    /// uid -> result per round
+    drivers: HashMap<usize, Vec<Option<RoundResult>>>,
+}
+
+impl Championship {
+    /// Points go to drivers, by uid, for their finishing position among classified entries in
+    /// their group at the end of each rally
+    pub fn compute(rallies: &[structures::Rally], uids: &UidMap, scheme: &PointsScheme) -> Self {
+        let mut groups: HashMap<String, GroupResults> = HashMap::new();
+
+        for rally in rallies {
+            let standings = Standings::compute(rally);
+            let Some(last) = standings.final_standings() else {
+                continue
+            };
+
+            let mut positions: HashMap<String, usize> = HashMap::new();
+            for standing in last.standings.iter().filter(|s| s.is_classified()) {
+                let Some(entry) = rally.entry_by_driver_number(standing.number) else {
+                    continue
+                };
+                if !scheme.scores(entry) {
+                    continue
+                }
+                let group = scheme.group(entry);
+                let position = positions.entry(group.clone()).or_default();
+                *position += 1;
+
+                let results = groups.entry(group).or_default();
+                if results.rounds.last().is_none_or(|r| r.slug != rally.slug) {
+                    results.rounds.push(Round { slug: rally.slug.clone(), title: rally.title.clone() });
+                }
+                let round = results.rounds.len() - 1;
+                let results = results.drivers.entry(entry.driverUID).or_default();
+                results.resize(round + 1, None);
+                results[round] = Some(RoundResult {
+                    position: *position,
+                    points: scheme.points(rally, *position),
+                    dropped: false,
+                });
+            }
+        }
+
+        let mut tables: Vec<_> = groups.into_iter().map(|(group, GroupResults { rounds, drivers })| {
+            let mut standings: Vec<_> = drivers.into_iter().map(|(uid, mut results)| {
+                results.resize(rounds.len(), None);
+                let total = drop_rounds(&mut results, scheme.best_of);
+                let name = structures::full_name(uids, uid);
+                ChampionshipStanding { position: 0, uid, name, total, results }
+            }).collect();
+
+            standings.sort_by(|a, b| b.total.total_cmp(&a.total).then_with(|| a.name.cmp(&b.name)));
+            for i in 0..standings.len() {
+                standings[i].position = if i > 0 && standings[i].total == standings[i - 1].total {
+                    standings[i - 1].position
+                } else {
+                    i + 1
+                };
+            }
+
+            ChampionshipTable { group, rounds, standings }
+        }).collect();
+        tables.sort_by(|a, b| a.group.cmp(&b.group));
+
+        Championship {
+            scheme: scheme.clone(),
+            tables,
+        }
+    }
+}
+
+/// Mark everything outside the best `best_of` results as dropped and total up the rest
+fn drop_rounds(results: &mut [Option<RoundResult>], best_of: Option<usize>) -> f32 {
+    let mut scored: Vec<_> = results.iter_mut().flatten().collect();
+    scored.sort_by(|a, b| b.points.total_cmp(&a.points));
+    let keep = best_of.unwrap_or(scored.len());
+    let mut total = 0.0;
+    for (i, result) in scored.into_iter().enumerate() {
+        if i < keep {
+            total += result.points;
+        } else {
+            result.dropped = true;
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entry, rally, stage, uids};
+    use serde_json::Value;
+
+    fn one_stage(slug: &str, entries: Vec<Value>) -> structures::Rally {
+        rally(slug, vec![stage("Forest", 5.0)], entries)
+    }
+
+    /// (uid, position, total) for each driver in the group's table
+    fn table(championship: &Championship, group: &str) -> Vec<(usize, usize, f32)> {
+        let table = championship.tables.iter().find(|t| t.group == group).unwrap();
+        table.standings.iter().map(|s| (s.uid, s.position, s.total)).collect()
+    }
+
+    fn groups(championship: &Championship) -> Vec<&str> {
+        championship.tables.iter().map(|t| t.group.as_str()).collect()
+    }
+
+    #[test]
+    fn positions_within_groups() {
+        let rallies = [one_stage("first", vec![
+            entry(1, 1, "O2WD", "National", &["5:00.0"]),
+            entry(2, 3, "O2WD", "Regional", &["5:05.0"]),
+            entry(3, 5, "O2WD", "National", &["5:10.0"]),
+        ])];
+        let championship = Championship::compute(&rallies, &uids(6), &PointsScheme::default());
+        assert_eq!(groups(&championship), vec!["National O2WD", "Regional O2WD"]);
+        // Second overall, but first among the Regionals
+        assert_eq!(table(&championship, "Regional O2WD"), vec![(3, 1, 25.0)]);
+        assert_eq!(table(&championship, "National O2WD"), vec![(1, 1, 25.0), (5, 2, 18.0)]);
+    }
+
+    #[test]
+    fn coefficients() {
+        let rallies = [
+            one_stage("first", vec![entry(1, 1, "O2WD", "National", &["5:00.0"])]),
+            one_stage("finale", vec![entry(1, 1, "O2WD", "National", &["5:00.0"])]),
+        ];
+        let scheme = PointsScheme {
+            coefficients: HashMap::from([("finale".to_string(), 2.0)]),
+            ..PointsScheme::default()
+        };
+        let championship = Championship::compute(&rallies, &uids(2), &scheme);
+        assert_eq!(table(&championship, "National O2WD"), vec![(1, 1, 75.0)]);
+    }
+
+    #[test]
+    fn filters() {
+        let rallies = [one_stage("first", vec![
+            entry(1, 1, "O2WD", "National", &["5:00.0"]),
+            entry(2, 3, "L4WD", "Regional", &["5:05.0"]),
+            entry(3, 5, "ClassX", "National", &["5:10.0"]),
+        ])];
+        let compute = |scheme: PointsScheme| Championship::compute(&rallies, &uids(6), &PointsScheme { grouping: Grouping::Overall, ..scheme });
+
+        let championship = compute(PointsScheme { categories: Some(vec![structures::Category::National]), ..PointsScheme::default() });
+        assert_eq!(table(&championship, "Overall"), vec![(1, 1, 25.0), (5, 2, 18.0)]);
+        // Class X doesn't say what it is, so never scores when filtering on it
+        let championship = compute(PointsScheme { drivetrain: Some(structures::Drivetrain::FourWheel), ..PointsScheme::default() });
+        assert_eq!(table(&championship, "Overall"), vec![(3, 1, 25.0)]);
+        let championship = compute(PointsScheme { tier: Some(structures::Tier::Open), ..PointsScheme::default() });
+        assert_eq!(table(&championship, "Overall"), vec![(1, 1, 25.0)]);
+    }
+
+    #[test]
+    fn drop_rounds() {
+        let rallies = [
+            one_stage("first", vec![entry(1, 1, "O2WD", "National", &["5:00.0"])]),
+            one_stage("second", vec![
+                entry(2, 3, "O2WD", "National", &["4:00.0"]),
+                entry(1, 1, "O2WD", "National", &["5:00.0"]),
+            ]),
+            one_stage("third", vec![entry(1, 1, "O2WD", "National", &["5:00.0"])]),
+        ];
+        let scheme = PointsScheme { best_of: Some(2), ..PointsScheme::default() };
+        let championship = Championship::compute(&rallies, &uids(4), &scheme);
+        assert_eq!(table(&championship, "National O2WD"), vec![(1, 1, 50.0), (3, 2, 25.0)]);
+
+        let standing = &championship.tables[0].standings[0];
+        let dropped: Vec<_> = standing.results.iter().map(|r| r.as_ref().unwrap().dropped).collect();
+        assert_eq!(dropped, vec![false, true, false]);
+        // Nothing to drop for someone who only has the one result
+        let standing = &championship.tables[0].standings[1];
+        assert!(matches!(standing.results[..], [None, Some(RoundResult { dropped: false, .. }), None]));
+    }
+
+    #[test]
+    fn tied_positions() {
+        let rallies = [
+            one_stage("first", vec![
+                entry(3, 5, "O2WD", "National", &["5:00.0"]),
+                entry(1, 1, "O2WD", "National", &["5:10.0"]),
+            ]),
+            one_stage("second", vec![
+                entry(1, 1, "O2WD", "National", &["5:00.0"]),
+                entry(3, 5, "O2WD", "National", &["5:10.0"]),
+                entry(2, 3, "O2WD", "National", &["5:20.0"]),
+            ]),
+        ];
+        let championship = Championship::compute(&rallies, &uids(6), &PointsScheme::default());
+        // Level on points share the position, in name order, and whoever's next skips it
+        assert_eq!(table(&championship, "National O2WD"), vec![(1, 1, 43.0), (5, 1, 43.0), (3, 3, 15.0)]);
+    }
+
+    #[test]
+    fn rounds_by_slug() {
+        let mut first = one_stage("sprint_1", vec![entry(1, 1, "O2WD", "National", &["5:00.0"])]);
+        let mut second = one_stage("sprint_2", vec![entry(1, 1, "O2WD", "National", &["5:00.0"])]);
+        first.title = "Sprint".to_string();
+        second.title = "Sprint".to_string();
+        let championship = Championship::compute(&[first, second], &uids(2), &PointsScheme::default());
+
+        let rounds: Vec<_> = championship.tables[0].rounds.iter().map(|r| (r.slug.as_str(), r.title.as_str())).collect();
+        assert_eq!(rounds, vec![("sprint_1", "Sprint"), ("sprint_2", "Sprint")]);
+        assert_eq!(table(&championship, "National O2WD"), vec![(1, 1, 50.0)]);
+    }
+}
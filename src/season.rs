@@ -0,0 +1,202 @@
+use serde::Serialize;
+use std::error::Error;
+
+use crate::spreadsheet::SpreadSheetError;
+use crate::structures::{self, Delta, UidMap};
+
+/// A driver against a set of rivals over every rally of a season they both drove in. People
+/// are matched by driverUID since car numbers change from rally to rally.
+#[derive(Serialize, Clone, Debug)]
+pub struct Season {
+    pub year: usize,
+    pub driver: String,
+    pub uid: usize,
+    pub rivals: Vec<Rivalry>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Rivalry {
+    pub uid: usize,
+    pub name: String,
+    /// In the order the rallies were given, see structures::sort_chronologically
+    pub rallies: Vec<HeadToHead>,
+    /// Mean of the per-rally deltas, signed as displayed so negative is slower
+    pub average: Option<f32>,
+    /// How the signed delta moved per rally over the season, positive is gaining on the rival
+    pub trend: Option<f32>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct HeadToHead {
+    pub rally: String,
+    pub slug: String,
+    pub driver_number: usize,
+    pub rival_number: usize,
+    /// Stages both had a comparable time on
+    pub stages: usize,
+    /// Seconds per mile over those stages
+    pub delta: Option<Delta>,
+    pub driver_wins: usize,
+    pub rival_wins: usize,
+    pub ties: usize,
+}
+
+impl HeadToHead {
+    fn new(rally: &structures::Rally, driver: &structures::Entry, rival: &structures::Entry) -> Self {
+        let stages = rally.comparable_stages(driver, rival);
+        let mut head_to_head = HeadToHead {
+            rally: rally.title.clone(),
+            slug: rally.slug.clone(),
+            driver_number: driver.number,
+            rival_number: rival.number,
+            stages: stages.len(),
+            delta: rally.pace_delta(driver, rival),
+            driver_wins: 0,
+            rival_wins: 0,
+            ties: 0,
+        };
+        for stage in stages {
+            match driver.time_on(stage).cmp(&rival.time_on(stage)) {
+                std::cmp::Ordering::Less => head_to_head.driver_wins += 1,
+                std::cmp::Ordering::Greater => head_to_head.rival_wins += 1,
+                std::cmp::Ordering::Equal => head_to_head.ties += 1,
+            }
+        }
+        head_to_head
+    }
+}
+
+impl Rivalry {
+    fn new(rallies: &[structures::Rally], uids: &UidMap, driver: usize, rival: &structures::Uid) -> Self {
+        let rallies: Vec<_> = rallies.iter()
+            .filter_map(|rally| match (rally.entry_by_driver_uid(driver), rally.entry_by_driver_uid(rival.uid)) {
+                (Some(d), Some(r)) => Some(HeadToHead::new(rally, d, r)),
+                _ => None,
+            })
+            .collect();
+
+        let deltas: Vec<_> = rallies.iter()
+            .filter_map(|h| h.delta.map(|d| d.signed()))
+            .collect();
+        let average = (!deltas.is_empty())
+            .then(|| deltas.iter().sum::<f32>() / deltas.len() as f32);
+
+        Rivalry {
+            uid: rival.uid,
+            name: structures::full_name(uids, rival.uid),
+            rallies,
+            average,
+            trend: trend(&deltas),
+        }
+    }
+}
+
+/// Least squares slope of the deltas against the order they happened in
+fn trend(deltas: &[f32]) -> Option<f32> {
+    if deltas.len() < 2 {
+        return None;
+    }
+    let n = deltas.len() as f32;
+    let mean_x = (n - 1.0) / 2.0;
+    let mean_y = deltas.iter().sum::<f32>() / n;
+    let (mut covariance, mut variance) = (0.0, 0.0);
+    for (x, y) in deltas.iter().enumerate() {
+        let dx = x as f32 - mean_x;
+        covariance += dx * (y - mean_y);
+        variance += dx * dx;
+    }
+    Some(covariance / variance)
+}
+
+impl Season {
+    pub fn build(year: usize, rallies: &[structures::Rally], uids: &UidMap, driver: usize, rivals: &[usize]) -> Result<Self, Box<dyn Error>> {
+        if !rallies.iter().any(|r| r.entry_by_driver_uid(driver).is_some()) {
+            return Err(Box::new(SpreadSheetError::new(format!("Driver {} did not race in {}", driver, year))));
+        }
+
+        let rivals = rivals.iter()
+            .filter(|uid| **uid != driver)
+            .map(|uid| uids.get(uid)
+                .map(|rival| Rivalry::new(rallies, uids, driver, rival))
+                .ok_or_else(|| Box::new(SpreadSheetError::new(format!("Unknown driver {}", uid)))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Season {
+            year,
+            driver: structures::full_name(uids, driver),
+            uid: driver,
+            rivals,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entry, rally, stage, uids};
+
+    /// #10 (uid 0) against #20 (uid 2): ahead by 2.5s/mile, then behind by 2s/mile, then ahead
+    /// by 0.5s/mile
+    fn season() -> Vec<structures::Rally> {
+        vec![
+            rally("first", vec![stage("One", 1.0), stage("Two", 1.0)], vec![
+                entry(10, 0, "O4WD", "National", &["01:00.0", "01:10.0"]),
+                entry(20, 2, "O4WD", "National", &["01:05.0", "01:10.0"]),
+            ]),
+            rally("second", vec![stage("One", 1.0)], vec![
+                entry(10, 0, "O4WD", "National", &["01:00.0"]),
+                entry(20, 2, "O4WD", "National", &["00:58.0"]),
+            ]),
+            rally("skipped", vec![stage("One", 1.0)], vec![
+                entry(10, 0, "O4WD", "National", &["01:00.0"]),
+            ]),
+            rally("third", vec![stage("One", 1.0)], vec![
+                entry(11, 0, "O4WD", "National", &["01:00.0"]),
+                entry(21, 2, "O4WD", "National", &["01:00.5"]),
+            ]),
+        ]
+    }
+
+    #[test]
+    fn head_to_head_counts() {
+        let season = Season::build(2025, &season(), &uids(9), 0, &[2]).unwrap();
+        let rivalry = &season.rivals[0];
+        let counts: Vec<_> = rivalry.rallies.iter()
+            .map(|h| (h.slug.as_str(), h.driver_number, h.rival_number, h.stages, h.driver_wins, h.rival_wins, h.ties))
+            .collect();
+        assert_eq!(counts, vec![
+            ("first", 10, 20, 2, 1, 0, 1),
+            ("second", 10, 20, 1, 0, 1, 0),
+            ("third", 11, 21, 1, 1, 0, 0),
+        ]);
+    }
+
+    #[test]
+    fn rivalry_average_and_trend() {
+        let season = Season::build(2025, &season(), &uids(9), 0, &[2]).unwrap();
+        let rivalry = &season.rivals[0];
+        let deltas: Vec<_> = rivalry.rallies.iter().map(|h| h.delta.unwrap().signed()).collect();
+        assert_eq!(deltas, vec![2.5, -2.0, 0.5]);
+        assert!((rivalry.average.unwrap() - 1.0 / 3.0).abs() < 1e-6);
+        assert!((rivalry.trend.unwrap() + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn least_squares_trend() {
+        assert_eq!(trend(&[]), None);
+        assert_eq!(trend(&[1.0]), None);
+        assert_eq!(trend(&[0.0, 1.0, 2.0]), Some(1.0));
+        assert_eq!(trend(&[3.0, 3.0, 3.0, 3.0]), Some(0.0));
+        // Best fit through (0, 1), (1, 0), (2, 4) is y = 1.5x + 0.17
+        assert!((trend(&[1.0, 0.0, 4.0]).unwrap() - 1.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn unknown_driver_name() {
+        let mut uids = uids(9);
+        uids.remove(&0);
+        let season = Season::build(2025, &season(), &uids, 0, &[2]).unwrap();
+        assert_eq!(season.driver, "Unknown (0)");
+        assert_eq!(season.rivals[0].name, "Person 2");
+    }
+}
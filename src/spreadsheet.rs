@@ -4,12 +4,29 @@ use serde_json;
 use std::fs;
 use std::fmt;
 use std::error::Error;
-use rust_xlsxwriter::{self as xls, Workbook, XlsxError};
+use rust_xlsxwriter::{self as xls, Workbook};
 
-use crate::structures::{self, UidMap};
+use crate::comparison::{Comparison, CompetitorStage, StageComparison};
+use crate::error::DataError;
+use crate::points::Championship;
+use crate::season::Season;
+use crate::standings::Standing;
+use crate::structures::{self, Delta, Precision, StageTime, UidMap};
 
 mod format {
     use super::xls;
+    use crate::structures::Precision;
+
+    /// Times are written as Excel durations, which are fractions of a day, shown to as many
+    /// places as they were given to
+    fn time_format(precision: Precision) -> String {
+        match precision.digits() {
+            0 => "[h]:mm:ss".to_string(),
+            digits => format!("[h]:mm:ss.{}", "0".repeat(digits)),
+        }
+    }
+    /// Seconds per mile
+    const DELTA_FORMAT: &str = "0.00";
     pub(super) struct Formats {
         pub bold: xls::Format,
         pub stage_name: xls::Format,
@@ -24,26 +41,30 @@ mod format {
         pub delta_faster: xls::Format,
         pub super_rally: xls::Format,
         pub driver_names: xls::Format,
+        pub position: xls::Format,
+        pub penalty: xls::Format,
+        pub total_time: xls::Format,
     }
 
     impl Formats {
         pub fn delta(&self, delta: crate::structures::Delta) -> &xls::Format {
             match delta.kind {
-                crate::structures::DeltaKind::Invalid |
-                    crate::structures::DeltaKind::Equal => &self.delta_invalid,
+                crate::structures::DeltaKind::Equal => &self.delta_invalid,
                 crate::structures::DeltaKind::Faster => &self.delta_faster,
                 crate::structures::DeltaKind::Slower => &self.delta
             }
         }
     }
 
-    pub(super) fn get_formats() -> Formats {
+    pub(super) fn get_formats(precision: Precision) -> Formats {
         let stage_time = xls::Format::new()
                 .set_align(xls::FormatAlign::Right)
-                .set_border_left(xls::FormatBorder::Thin);
+                .set_border_left(xls::FormatBorder::Thin)
+                .set_num_format(time_format(precision));
 
         let delta = xls::Format::new()
-            .set_border_right(xls::FormatBorder::Thin);
+            .set_border_right(xls::FormatBorder::Thin)
+            .set_num_format(DELTA_FORMAT);
 
         Formats {
             bold: xls::Format::new()
@@ -75,38 +96,75 @@ mod format {
                 .set_border(xls::FormatBorder::Medium)
                 .set_bold(),
 
+            penalty: stage_time.clone()
+                .set_font_color(xls::Color::Red),
+
+            total_time: stage_time.clone()
+                .set_bold(),
+
+            position: xls::Format::new()
+                .set_align(xls::FormatAlign::Center)
+                .set_border_right(xls::FormatBorder::Thin),
+
             stage_time,
             delta,
         }
     }
 }
 
-const SNEAK_ATTACK_BASE: &'static str = "https://sneakattackrally.com/ARACombinerThing/data";
-fn fetch_sneakattack_json<'a, T: serde::de::DeserializeOwned>(name: &'a str) -> Result<T, reqwest::Error> {
-    let path = format!("{}/{}", SNEAK_ATTACK_BASE, name);
-    let body = reqwest::blocking::get(path)?
-        .json();
-    body
+pub const SNEAK_ATTACK_BASE: &str = "https://sneakattackrally.com/ARACombinerThing/data";
+
+async fn fetch_data_file(base: &str, name: &str) -> Result<String, DataError> {
+    let path = format!("{}/{}", base, name);
+    let fetch = async { reqwest::get(path).await?.error_for_status()?.text().await };
+    fetch.await.map_err(|e| DataError::read(name, e))
 }
 
-pub fn load_sneakattack_json<'a, T: serde::de::DeserializeOwned>(name: &'a str) -> Result<T, ()> {
-    let fh = fs::File::open(name).unwrap();
-    let res = serde_json::from_reader(fh);
-    Ok(res.unwrap())
+fn load_data_file(name: &str) -> Result<String, DataError> {
+    fs::read_to_string(name).map_err(|e| DataError::read(name, e))
 }
 
-fn get_rallies_by_year(year: usize) -> Result<(), ()> {
-    Ok(())
+/// Fetch one of the data files from `base`, which is normally SNEAK_ATTACK_BASE
+pub async fn fetch_sneakattack_json<T: serde::de::DeserializeOwned>(base: &str, name: &str) -> Result<T, DataError> {
+    parse_sneakattack_json(name, &fetch_data_file(base, name).await?)
+}
+
+pub fn load_sneakattack_json<T: serde::de::DeserializeOwned>(name: &str) -> Result<T, DataError> {
+    parse_sneakattack_json(name, &load_data_file(name)?)
+}
+
+/// `name` is only used to say where any problem is
+pub fn parse_sneakattack_json<T: serde::de::DeserializeOwned>(name: &str, data: &str) -> Result<T, DataError> {
+    let value: serde_json::Value = serde_json::from_str(data)
+        .map_err(|source| DataError::Syntax { file: name.to_string(), source })?;
+    serde_path_to_error::deserialize(&value).map_err(|e| DataError::invalid(name, &value, e))
+}
+
+/// The rallies in a data file that could be read, and what was wrong with the ones that couldn't
+pub struct RallyFile {
+    pub rallies: Vec<structures::Rally>,
+    pub errors: Vec<DataError>,
 }
 
-pub struct RallyData {
+/// Like parse_sneakattack_json, except one bad rally doesn't stop the rest of the file loading
+pub fn parse_rallies(name: &str, data: &str) -> Result<RallyFile, DataError> {
+    let values: Vec<serde_json::Value> = parse_sneakattack_json(name, data)?;
+    let mut file = RallyFile { rallies: vec![], errors: vec![] };
+    for value in values {
+        match serde_path_to_error::deserialize(&value) {
+            Ok(rally) => file.rallies.push(rally),
+            Err(e) => file.errors.push(DataError::invalid(name, &value, e)),
+        }
+    }
+    Ok(file)
 }
 
-pub fn build_data(rally: &structures::Rally, driver: usize, benchmarks: &[usize]) -> RallyData {
-    let driver_entry = rally.entry_by_driver_number(driver).unwrap();
-    let driver_class = driver_entry.class;
+pub fn load_rallies(name: &str) -> Result<RallyFile, DataError> {
+    parse_rallies(name, &load_data_file(name)?)
+}
 
-    RallyData {}
+pub async fn fetch_rallies(base: &str, name: &str) -> Result<RallyFile, DataError> {
+    parse_rallies(name, &fetch_data_file(base, name).await?)
 }
 
 #[derive(Debug)]
@@ -130,8 +188,202 @@ impl fmt::Display for SpreadSheetError {
 
 impl Error for SpreadSheetError {}
 
-pub fn build_stage_with_splits(rally: &structures::Rally, uids: &UidMap, driver: &structures::Entry, benchmarks: &Vec<&structures::Entry>, stage: &structures::Stage, stage_index: usize, sheet: &mut xls::Worksheet) -> Result<(), Box<dyn Error>> {
-    let formats = format::get_formats();
+/// Where things go on the overview sheet, which the charts and class field refer back to
+mod overview_layout {
+    pub const STAGE_START_ROW: u32 = 2;
+    pub const LENGTH_COLUMN: u16 = 1;
+    pub const DRIVER_COLUMN: u16 = 2;
+    pub const PENALTY_COLUMN: u16 = 3;
+    pub const POSITION_COLUMN: u16 = 4;
+    /// Then a time and a delta column for each benchmark
+    const BENCHMARK_START_COLUMN: u16 = 5;
+
+    pub fn stage_row(stage: usize) -> u32 {
+        STAGE_START_ROW + stage as u32
+    }
+
+    pub fn benchmark_column(benchmark: usize) -> u16 {
+        BENCHMARK_START_COLUMN + (benchmark * 2) as u16
+    }
+
+    pub fn delta_column(benchmark: usize) -> u16 {
+        benchmark_column(benchmark) + 1
+    }
+}
+
+/// Where things go on a split sheet, which the charts refer back to
+mod split_layout {
+    pub const DISTANCE_ROW: u32 = 1;
+    pub const DRIVER_ROW: u32 = 2;
+    const BENCHMARK_START_ROW: u32 = 3;
+
+    pub fn benchmark_row(benchmark: usize) -> u32 {
+        BENCHMARK_START_ROW + benchmark as u32
+    }
+
+    /// Then a sector and a cumulative delta column for each split
+    pub fn split_column(split: usize) -> u16 {
+        (split * 3 + 3) as u16
+    }
+
+    pub fn sector_delta_column(split: usize) -> u16 {
+        split_column(split) + 1
+    }
+
+    pub fn cumulative_delta_column(split: usize) -> u16 {
+        split_column(split) + 2
+    }
+}
+
+/// Where things go on the class field sheet, one row per entry
+mod class_field_layout {
+    pub const NUMBER_COLUMN: u16 = 0;
+    pub const NAME_COLUMN: u16 = 1;
+    const ENTRY_START_ROW: u32 = 2;
+    const STAGE_START_COLUMN: u16 = 2;
+
+    pub fn entry_row(entry: usize) -> u32 {
+        ENTRY_START_ROW + entry as u32
+    }
+
+    /// Then a time, total, position and delta column for each stage
+    pub fn stage_column(stage: usize) -> u16 {
+        STAGE_START_COLUMN + (stage * 4) as u16
+    }
+
+    pub fn total_column(stage: usize) -> u16 {
+        stage_column(stage) + 1
+    }
+
+    pub fn position_column(stage: usize) -> u16 {
+        stage_column(stage) + 2
+    }
+
+    pub fn delta_column(stage: usize) -> u16 {
+        stage_column(stage) + 3
+    }
+}
+
+/// Where things go in each stage's table on the stage history sheet
+mod stage_history_layout {
+    pub const YEAR_COLUMN: u16 = 0;
+    pub const RALLY_COLUMN: u16 = 1;
+    pub const STAGE_COLUMN: u16 = 2;
+    pub const LENGTH_COLUMN: u16 = 3;
+    pub const DRIVER_COLUMN: u16 = 4;
+    /// Then a time and a delta column for each benchmark
+    const BENCHMARK_START_COLUMN: u16 = 5;
+
+    pub fn benchmark_column(benchmark: usize) -> u16 {
+        BENCHMARK_START_COLUMN + (benchmark * 2) as u16
+    }
+
+    pub fn delta_column(benchmark: usize) -> u16 {
+        benchmark_column(benchmark) + 1
+    }
+}
+
+/// Excel stores durations as fractions of a day
+fn excel_duration(time: StageTime) -> f64 {
+    time.as_secs_f64() / 86400.0
+}
+
+/// Write a time as a number Excel can sum and sort
+fn write_time(sheet: &mut xls::Worksheet, row: u32, col: u16, time: StageTime, format: &xls::Format) -> Result<(), Box<dyn Error>> {
+    sheet.write_with_format(row, col, excel_duration(time), format)?;
+    Ok(())
+}
+
+/// A split time, left blank if it wasn't recorded
+fn write_split(sheet: &mut xls::Worksheet, row: u32, col: u16, split: Option<StageTime>, format: &xls::Format) -> Result<(), Box<dyn Error>> {
+    match split {
+        Some(time) => write_time(sheet, row, col, time, format)?,
+        None => {
+            sheet.write_blank(row, col, format)?;
+        },
+    }
+    Ok(())
+}
+
+/// A stage time, or the reason there isn't one
+fn write_stage(sheet: &mut xls::Worksheet, row: u32, col: u16, stage: &CompetitorStage, format: &xls::Format) -> Result<(), Box<dyn Error>> {
+    match (stage.note(), stage.result.recorded()) {
+        (Some(note), _) => sheet.write_with_format(row, col, note, format)?,
+        (None, Some(time)) => return write_time(sheet, row, col, time, format),
+        (None, None) => sheet.write_blank(row, col, format)?,
+    };
+    Ok(())
+}
+
+/// Deltas go in with the sign they're displayed with, negative when slower
+fn delta_value(delta: Delta) -> f64 {
+    delta.signed() as f64
+}
+
+/// How the workbook gets written
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WorkbookOptions {
+    /// Write deltas and totals as formulas over the time and length cells, so correcting a
+    /// time or adding a penalty in the sheet carries through
+    pub formulas: bool,
+}
+
+/// Seconds per mile between two times, signed like the static deltas. `cells` are everything
+/// the times are built from, so a missing or DNF time leaves the delta blank.
+fn delta_formula(driver: &str, benchmark: &str, length: &str, cells: &[String]) -> String {
+    let checks: Vec<_> = cells.iter().map(|c| format!("ISNUMBER({})", c)).collect();
+    format!("IF(AND({}),({}-({}))*86400/({}),\"\")", checks.join(","), benchmark, driver, length)
+}
+
+/// Either the live formula or the precomputed delta
+fn write_delta(sheet: &mut xls::Worksheet, row: u32, col: u16, delta: Option<Delta>, formula: Option<String>, formats: &format::Formats) -> Result<(), Box<dyn Error>> {
+    match (formula, delta) {
+        (Some(formula), _) => {
+            let result = delta.map(|d| delta_value(d).to_string()).unwrap_or_default();
+            sheet.write_formula_with_format(row, col, xls::Formula::new(formula).set_result(result), &formats.delta)?;
+            // The value can change, so faster has to be highlighted by Excel rather than us
+            let cell = xls::utility::row_col_to_cell(row, col);
+            let faster = xls::ConditionalFormatFormula::new()
+                .set_rule(format!("=AND(ISNUMBER({cell}),{cell}>0)").as_str())
+                .set_format(&formats.delta_faster);
+            sheet.add_conditional_format(row, col, row, col, &faster)?;
+        },
+        (None, Some(delta)) => {
+            sheet.write_with_format(row, col, delta_value(delta), formats.delta(delta))?;
+        },
+        (None, None) => (),
+    }
+    Ok(())
+}
+
+/// Stage times are picked out when they're the fastest in class or category, or can't be used
+fn format_time<'a>(formats: &'a format::Formats, competitor: &CompetitorStage, stage: &StageComparison) -> &'a xls::Format {
+    let time = competitor.result.time();
+    if competitor.super_rally {
+        &formats.super_rally
+    } else if time.is_none() {
+        &formats.invalid_time
+    } else if time == stage.class_fastest {
+        &formats.overall_class_win
+    } else if time == stage.category_fastest {
+        &formats.class_win
+    } else {
+        &formats.stage_time
+    }
+}
+
+/// Position after a stage as shown on the sheets, overall then class
+pub fn position_text(standing: &Standing) -> Option<String> {
+    match (standing.overall, standing.class) {
+        (Some(overall), Some(class)) => Some(format!("{} ({})", overall, class)),
+        _ if standing.retired => Some("DNF".to_string()),
+        _ => None,
+    }
+}
+
+pub fn build_stage_with_splits(comparison: &Comparison, stage_index: usize, sheet: &mut xls::Worksheet, options: WorkbookOptions) -> Result<(), Box<dyn Error>> {
+    let formats = format::get_formats(comparison.time_precision());
+    let stage = &comparison.stages[stage_index];
 
     // Other way around, we'll do drivers per row
     sheet.set_column_width(0, 18)?;
@@ -141,17 +393,15 @@ pub fn build_stage_with_splits(rally: &structures::Rally, uids: &UidMap, driver:
 
     sheet.write_with_format(1, 0, "Team", &formats.heading)?;
 
-    let stage_splits = stage.splits_with_finish();
-    for (i, split) in stage_splits.iter().enumerate() {
-        let col = ((1+i) * 3) as u16;
-        sheet.write_with_format(1, col,
+    for (i, split) in stage.split_distances.iter().enumerate() {
+        sheet.write_with_format(split_layout::DISTANCE_ROW, split_layout::split_column(i),
             *split,
             &formats.stage_length)?;
-        sheet.write_with_format(1, col+1,
+        sheet.write_with_format(split_layout::DISTANCE_ROW, split_layout::sector_delta_column(i),
             "Diff s/mi",
             &formats.stage_name)?;
 
-        sheet.write_with_format(1, col+2,
+        sheet.write_with_format(split_layout::DISTANCE_ROW, split_layout::cumulative_delta_column(i),
             "Cumulative s/mi",
             &formats.stage_name)?;
     }
@@ -159,157 +409,439 @@ pub fn build_stage_with_splits(rally: &structures::Rally, uids: &UidMap, driver:
 
 
     let name_column = 0;
-    let driver_row = 2;
+    let driver_row = split_layout::DRIVER_ROW;
     sheet.write_with_format(driver_row, name_column,
-        driver.names(&uids),
+        &comparison.driver.names,
         &formats.heading)?;
-    let driver_splits = &driver.splits_with_finish()[stage_index];
-    let driver_sectors = &driver.sectors_with_finish()[stage_index];
-    for (n, split) in driver_splits.iter().enumerate() {
-        sheet.write_with_format(driver_row,
-            (n*3 + 3) as u16,
-            split.to_string(),
+    for (n, split) in stage.driver.splits.iter().enumerate() {
+        write_split(sheet, driver_row,
+            split_layout::split_column(n),
+            *split,
             &formats.stage_time)?;
     }
 
-    let benchmark_start = 3;
-    for (row, bm) in benchmarks.iter().enumerate() {
-        let row = (row + benchmark_start) as u32;
+    for (i, (bm, bm_stage)) in comparison.benchmarks.iter().zip(stage.benchmarks.iter()).enumerate() {
+        let row = split_layout::benchmark_row(i);
         sheet.write_with_format(
             row,
             name_column,
-            bm.names(&uids),
+            &bm.names,
             &formats.bold)?;
-        let bm_splits = &bm.splits_with_finish()[stage_index];
-        let bm_sectors = &bm.sectors_with_finish()[stage_index];
-        let mut prev_split_distance = 0.0;
-        for (n, ((split, sector), split_distance)) in bm_splits.iter().zip(bm_sectors.iter()).zip(stage_splits.iter()).enumerate() {
-            let driver_split = driver_splits[n];
-            let driver_sector = driver_sectors[n];
-            let col = (n*3 + 3) as u16;
-            sheet.write_with_format(row,
-                col,
-                split.to_string(),
+        for (n, (split, split_delta)) in bm_stage.stage.splits.iter().zip(bm_stage.split_deltas.iter()).enumerate() {
+            write_split(sheet, row,
+                split_layout::split_column(n),
+                *split,
                 &formats.stage_time)?;
 
-            if split.is_valid() && driver_split.is_valid() {
-                let cumulative_delta = driver_split.diff_per_mile(&split, *split_distance);
-
-                let this_sector = split_distance - prev_split_distance;
-                let sector_delta = driver_sector.diff_per_mile(&sector, this_sector);
-                sheet.write_with_format(row,
-                    col+1,
-                    sector_delta.to_string(),
-                    formats.delta(sector_delta))?;
-                sheet.write_with_format(row,
-                    col+2,
-                    cumulative_delta.to_string(),
-                    formats.delta(cumulative_delta))?;
+            if options.formulas {
+                if stage.driver.super_rally || bm_stage.stage.super_rally {
+                    continue
+                }
+                let cell = |row, n: usize| xls::utility::row_col_to_cell(row, split_layout::split_column(n));
+                let distance_row = split_layout::DISTANCE_ROW;
+                let (driver, benchmark, distance) = (cell(driver_row, n), cell(row, n), cell(distance_row, n));
+                let (sector_driver, sector_benchmark, sector_distance, cells) = if n == 0 {
+                    (driver.clone(), benchmark.clone(), distance.clone(), vec![driver.clone(), benchmark.clone()])
+                } else {
+                    let (prev_driver, prev_benchmark, prev_distance) = (cell(driver_row, n - 1), cell(row, n - 1), cell(distance_row, n - 1));
+                    (format!("{}-{}", driver, prev_driver),
+                     format!("{}-{}", benchmark, prev_benchmark),
+                     format!("{}-{}", distance, prev_distance),
+                     vec![driver.clone(), benchmark.clone(), prev_driver, prev_benchmark])
+                };
+                write_delta(sheet, row, split_layout::sector_delta_column(n), split_delta.sector,
+                    Some(delta_formula(&sector_driver, &sector_benchmark, &sector_distance, &cells)), &formats)?;
+                write_delta(sheet, row, split_layout::cumulative_delta_column(n), split_delta.cumulative,
+                    Some(delta_formula(&driver, &benchmark, &distance, &[driver.clone(), benchmark.clone()])), &formats)?;
+            } else if let (Some(sector_delta), Some(cumulative_delta)) = (split_delta.sector, split_delta.cumulative) {
+                write_delta(sheet, row, split_layout::sector_delta_column(n), Some(sector_delta), None, &formats)?;
+                write_delta(sheet, row, split_layout::cumulative_delta_column(n), Some(cumulative_delta), None, &formats)?;
             }
-            prev_split_distance = *split_distance;
         }
     }
     Ok(())
 }
 
-pub fn build_overview(rally: &structures::Rally, uids: &UidMap, driver: &structures::Entry, benchmarks: &Vec<&structures::Entry>,  sheet: &mut xls::Worksheet) -> Result<(), Box<dyn Error>> {
-    let formats = format::get_formats();
+pub fn build_overview(comparison: &Comparison, sheet: &mut xls::Worksheet, options: WorkbookOptions) -> Result<(), Box<dyn Error>> {
+    use overview_layout::{benchmark_column, delta_column, stage_row, DRIVER_COLUMN, LENGTH_COLUMN, PENALTY_COLUMN, POSITION_COLUMN, STAGE_START_ROW};
+
+    let formats = format::get_formats(comparison.time_precision());
 
-    let stage_start_row = 2;
     // Title/Stage names columns
     sheet.set_column_width(0, 18)?;
-    sheet.write_with_format(0, 0, &rally.title, &formats.bold)?;
+    sheet.write_with_format(0, 0, &comparison.rally, &formats.bold)?;
     sheet.write_with_format(1, 0, "Stage Name", &formats.heading)?;
-    sheet.write_with_format(1, 1, "Length", &formats.heading)?;
+    sheet.write_with_format(1, LENGTH_COLUMN, "Length", &formats.heading)?;
 
     // Milage column
-    sheet.set_column_width(1, 8)?;
-
-    let format_time = |time: &structures::StageTime, overall_win: &Option<structures::StageTime>, category_class_win: &Option<structures::StageTime>| {
-        if !time.is_valid() {
-            return &formats.invalid_time;
-        } else if Some(time) == overall_win.as_ref() {
-            return &formats.overall_class_win;
-        } else if Some(time) == category_class_win.as_ref() {
-            return &formats.class_win;
-        } else {
-            return &formats.stage_time;
-        }
-    };
+    sheet.set_column_width(LENGTH_COLUMN, 8)?;
 
-    let driver_column = 2;
-    let benchmark_start_column = 4;
+    let format_time = |competitor, stage| format_time(&formats, competitor, stage);
 
-    sheet.write_with_format(1, driver_column,
-        format!("{}", driver.number), // TODO(richo) Do the uid lookup thing to figure out who we are
+    sheet.write_with_format(1, DRIVER_COLUMN,
+        format!("{}", comparison.driver.number), // TODO(richo) Do the uid lookup thing to figure out who we are
+        &formats.heading)?;
+    sheet.write_with_format(1, PENALTY_COLUMN,
+        "Penalties",
+        &formats.heading)?;
+    sheet.write_with_format(1, POSITION_COLUMN,
+        "Pos (Class)",
         &formats.heading)?;
-    for (i, benchmark) in benchmarks.iter().enumerate() {
+    for (i, benchmark) in comparison.benchmarks.iter().enumerate() {
 
-        sheet.merge_range(0, benchmark_start_column + (i *2 ) as u16,
-                              0, benchmark_start_column + 1 + (i * 2) as u16,
-                              &benchmark.names(&uids),
+        sheet.merge_range(0, benchmark_column(i),
+                              0, delta_column(i),
+                              &benchmark.names,
             &formats.driver_names)?;
-        sheet.write_with_format(1, benchmark_start_column + (i * 2) as u16,
+        sheet.write_with_format(1, benchmark_column(i),
             format!("{}", benchmark.number),
             &formats.heading)?;
-        sheet.write_with_format(1, benchmark_start_column + 1 + (i * 2) as u16,
+        sheet.write_with_format(1, delta_column(i),
             "Diff s/mi",
             &formats.heading)?;
     }
 
-    for (stage_number, stage) in rally.stages.iter().enumerate() {
-        sheet.write_with_format(stage_start_row + stage_number as u32, 0, &stage.name, &formats.stage_name)?;
-        sheet.write_with_format(stage_start_row + stage_number as u32, 1, stage.length, &formats.stage_length)?;
+    for (stage_number, stage) in comparison.stages.iter().enumerate() {
+        let row = stage_row(stage_number);
+        sheet.write_with_format(row, 0, &stage.name, &formats.stage_name)?;
+        sheet.write_with_format(row, LENGTH_COLUMN, stage.length, &formats.stage_length)?;
+
+        let driver = &stage.driver;
+        write_stage(sheet, row, DRIVER_COLUMN, driver, format_time(driver, stage))?;
+        if driver.penalties > StageTime::zero() {
+            write_time(sheet, row, PENALTY_COLUMN,
+                driver.penalties,
+                &formats.penalty)?;
+        }
+        if let Some(position) = driver.position.as_ref().and_then(position_text) {
+            sheet.write_with_format(row, POSITION_COLUMN,
+                position,
+                &formats.position)?;
+        }
+
+        for (i, benchmark) in stage.benchmarks.iter().enumerate() {
+            write_stage(sheet, row,
+                benchmark_column(i),
+                &benchmark.stage,
+                format_time(&benchmark.stage, stage))?;
+            let formula = (options.formulas && !(driver.super_rally || benchmark.stage.super_rally)).then(|| {
+                let driver = xls::utility::row_col_to_cell(row, DRIVER_COLUMN);
+                let benchmark = xls::utility::row_col_to_cell(row, benchmark_column(i));
+                let length = xls::utility::row_col_to_cell(row, LENGTH_COLUMN);
+                delta_formula(&driver, &benchmark, &length, &[driver.clone(), benchmark.clone()])
+            });
+            write_delta(sheet, row,
+                delta_column(i),
+                benchmark.delta,
+                formula,
+                &formats)?;
+        }
+    }
+
+    // Totals at the foot of the sheet, before and after penalties
+    let totals_row = stage_row(comparison.stages.len());
+    sheet.write_with_format(totals_row, 0, "Stage Time", &formats.heading)?;
+    sheet.write_with_format(totals_row + 1, 0, "Penalties", &formats.heading)?;
+    sheet.write_with_format(totals_row + 2, 0, "Total", &formats.heading)?;
+
+    let mut columns = vec![(&comparison.driver, DRIVER_COLUMN)];
+    for (i, benchmark) in comparison.benchmarks.iter().enumerate() {
+        columns.push((benchmark, benchmark_column(i)));
+    }
+    for (competitor, column) in columns {
+        let Some(standing) = &competitor.result else {
+            continue
+        };
+        if options.formulas && !comparison.stages.is_empty() {
+            let last_row = totals_row - 1;
+            let sum = |col| format!("SUM({})", xls::utility::cell_range(STAGE_START_ROW, col, last_row, col));
+            let total = |row| xls::utility::row_col_to_cell(row, column);
+            sheet.write_formula_with_format(totals_row, column,
+                xls::Formula::new(sum(column)).set_result(excel_duration(standing.unpenalised).to_string()),
+                &formats.stage_time)?;
+            // Only the driver has a penalty column to add up
+            if column == DRIVER_COLUMN {
+                sheet.write_formula_with_format(totals_row + 1, column,
+                    xls::Formula::new(sum(PENALTY_COLUMN)).set_result(excel_duration(standing.penalties).to_string()),
+                    &formats.penalty)?;
+            } else {
+                sheet.write_with_format(totals_row + 1, column,
+                    excel_duration(standing.penalties),
+                    &formats.penalty)?;
+            }
+            sheet.write_formula_with_format(totals_row + 2, column,
+                xls::Formula::new(format!("{}+{}", total(totals_row), total(totals_row + 1)))
+                    .set_result(excel_duration(standing.total).to_string()),
+                &formats.total_time)?;
+            continue
+        }
+        sheet.write_with_format(totals_row, column,
+            excel_duration(standing.unpenalised),
+            &formats.stage_time)?;
+        sheet.write_with_format(totals_row + 1, column,
+            excel_duration(standing.penalties),
+            &formats.penalty)?;
+        sheet.write_with_format(totals_row + 2, column,
+            excel_duration(standing.total),
+            &formats.total_time)?;
+    }
+
+    Ok(())
+}
+
+pub fn build_stage_history(comparison: &Comparison, sheet: &mut xls::Worksheet) -> Result<(), Box<dyn Error>> {
+    let formats = format::get_formats(comparison.time_precision());
 
-        let overall_win = rally.entries.iter()
-            .filter(|x| x.class == driver.class)
-            .map(|x| x.times[stage_number])
-            .filter(|x| x.is_valid())
-            .min();
+    use stage_history_layout::{benchmark_column, delta_column, DRIVER_COLUMN, LENGTH_COLUMN, RALLY_COLUMN, STAGE_COLUMN, YEAR_COLUMN};
 
-        let class_win = rally.entries.iter()
-            .filter(|x| x.class == driver.class)
-            .filter(|x| x.category == driver.category)
-            .map(|x| x.times[stage_number])
-            .filter(|x| x.is_valid())
-            .min();
+    sheet.set_column_width(YEAR_COLUMN, 8)?;
+    sheet.set_column_width(RALLY_COLUMN, 36)?;
+    sheet.set_column_width(STAGE_COLUMN, 24)?;
+    sheet.write_with_format(0, 0, &comparison.rally, &formats.bold)?;
 
-        let driver_time = driver.times[stage_number];
+    let mut row = 2;
+    for history in &comparison.stage_history {
+        let stage = &comparison.stages[history.stage];
+        sheet.write_with_format(row, 0, format!("SS{} {}", history.stage + 1, stage.name), &formats.bold)?;
+        row += 1;
 
-        let mut fmt = format_time(&driver_time, &overall_win, &class_win);
-        if driver.colors[stage_number] == structures::BoxColor::Red {
-            fmt = &formats.super_rally;
+        sheet.write_with_format(row, YEAR_COLUMN, "Year", &formats.heading)?;
+        sheet.write_with_format(row, RALLY_COLUMN, "Rally", &formats.heading)?;
+        sheet.write_with_format(row, STAGE_COLUMN, "Stage", &formats.heading)?;
+        sheet.write_with_format(row, LENGTH_COLUMN, "Length", &formats.heading)?;
+        sheet.write_with_format(row, DRIVER_COLUMN, &comparison.driver.names, &formats.heading)?;
+        for (i, benchmark) in comparison.benchmarks.iter().enumerate() {
+            sheet.write_with_format(row, benchmark_column(i), &benchmark.names, &formats.heading)?;
+            sheet.write_with_format(row, delta_column(i), "Diff s/mi", &formats.heading)?;
         }
-        sheet.write_with_format(stage_start_row + stage_number as u32, driver_column, driver_time.to_string(), fmt)?;
+        row += 1;
 
-        for (i, benchmark) in benchmarks.iter().enumerate() {
-            let benchmark_time = benchmark.times[stage_number];
-            let mut fmt = format_time(&benchmark_time, &overall_win, &class_win);
-            if benchmark.colors[stage_number] == structures::BoxColor::Red {
-                fmt = &formats.super_rally;
+        for run in &history.runs {
+            sheet.write_with_format(row, YEAR_COLUMN, run.year as u32, &formats.stage_name)?;
+            sheet.write_with_format(row, RALLY_COLUMN, &run.rally, &formats.stage_name)?;
+            sheet.write_with_format(row, STAGE_COLUMN, format!("SS{} {}", run.stage + 1, run.name), &formats.stage_name)?;
+            sheet.write_with_format(row, LENGTH_COLUMN, run.length, &formats.stage_length)?;
+            match run.driver {
+                Some(time) => write_time(sheet, row, DRIVER_COLUMN, time, &formats.stage_time)?,
+                None => {
+                    sheet.write_blank(row, DRIVER_COLUMN, &formats.invalid_time)?;
+                },
             }
-            sheet.write_with_format(stage_start_row + stage_number as u32,
-                benchmark_start_column + (i * 2) as u16,
-                benchmark_time.to_string(),
-                fmt)?;
-            if benchmark_time.is_valid() && driver_time.is_valid() {
-                let delta = driver_time.diff_per_mile(&benchmark_time, stage.length);
-                sheet.write_with_format(stage_start_row + stage_number as u32,
-                    benchmark_start_column + 1 + (i * 2) as u16,
-                    delta.to_string(),
-                    formats.delta(delta))?;
+            for (i, benchmark) in run.benchmarks.iter().enumerate() {
+                let col = benchmark_column(i);
+                let Some(benchmark) = benchmark else {
+                    sheet.write_blank(row, col, &formats.invalid_time)?;
+                    continue
+                };
+                write_time(sheet, row, col, benchmark.time, &formats.stage_time)?;
+                if let Some(delta) = benchmark.delta {
+                    sheet.write_with_format(row, delta_column(i), delta_value(delta), formats.delta(delta))?;
+                }
             }
+            row += 1;
         }
+        row += 1;
     }
 
     Ok(())
 }
 
+/// Gap in seconds the driver has over a benchmark after each stage, negative when behind.
+/// Stages either of them didn't drive don't move it.
+fn cumulative_gaps(comparison: &Comparison, benchmark: usize) -> Vec<f64> {
+    let mut gap = 0.0;
+    comparison.stages.iter().map(|stage| {
+        let (driver, benchmark) = (&stage.driver, &stage.benchmarks[benchmark].stage);
+        if let (Some(driver), Some(benchmark)) = (driver.result.time(), benchmark.result.time()) {
+            gap += benchmark.as_secs_f64() - driver.as_secs_f64();
+        }
+        gap
+    }).collect()
+}
+
+/// Charts of the comparison. The data they plot either lives on the overview and split sheets
+/// or is worked out from them by formulas here, so they follow any edits to those.
+pub fn build_charts(comparison: &Comparison, sheet: &mut xls::Worksheet) -> Result<(), Box<dyn Error>> {
+    let formats = format::get_formats(comparison.time_precision());
+    let overview = prepare_slug(&comparison.slug);
+    let quoted = xls::utility::quote_sheet_name(&overview);
+
+    use overview_layout::{benchmark_column, delta_column, stage_row, DRIVER_COLUMN, STAGE_START_ROW};
+
+    let last_stage_row = stage_row(comparison.stages.len()) - 1;
+
+    sheet.set_column_width(0, 18)?;
+    let chart_column = comparison.benchmarks.len() as u16 + 2;
+    let mut chart_row = 0;
+    let mut insert = |sheet: &mut xls::Worksheet, chart: &mut xls::Chart| -> Result<(), Box<dyn Error>> {
+        chart.set_width(720);
+        sheet.insert_chart(chart_row, chart_column, chart)?;
+        chart_row += 16;
+        Ok(())
+    };
+
+    // Cumulative gap, summed up from the overview times
+    sheet.write_with_format(0, 0, "Cumulative gap (s)", &formats.bold)?;
+    sheet.write_with_format(1, 0, "Stage Name", &formats.heading)?;
+    let mut cumulative = xls::Chart::new_line();
+    cumulative.title().set_name("Cumulative gap");
+    cumulative.y_axis().set_name("Seconds ahead");
+    for (i, benchmark) in comparison.benchmarks.iter().enumerate() {
+        let col = 1 + i as u16;
+        sheet.write_with_format(1, col, &benchmark.names, &formats.heading)?;
+        let gaps = cumulative_gaps(comparison, i);
+        for (stage_number, stage) in comparison.stages.iter().enumerate() {
+            let row = stage_row(stage_number);
+            if i == 0 {
+                sheet.write_formula_with_format(row, 0,
+                    xls::Formula::new(format!("{}!{}", quoted, xls::utility::row_col_to_cell(row, 0))).set_result(&stage.name),
+                    &formats.stage_name)?;
+            }
+            let previous = if stage_number == 0 { "0".to_string() } else { xls::utility::row_col_to_cell(row - 1, col) };
+            let formula = if stage.driver.super_rally || stage.benchmarks[i].stage.super_rally {
+                previous
+            } else {
+                let driver = format!("{}!{}", quoted, xls::utility::row_col_to_cell(row, DRIVER_COLUMN));
+                let benchmark = format!("{}!{}", quoted, xls::utility::row_col_to_cell(row, benchmark_column(i)));
+                format!("{previous}+IF(AND(ISNUMBER({driver}),ISNUMBER({benchmark})),({benchmark}-{driver})*86400,0)")
+            };
+            sheet.write_formula_with_format(row, col,
+                xls::Formula::new(formula).set_result(gaps[stage_number].to_string()),
+                &formats.delta)?;
+        }
+        cumulative.add_series()
+            .set_name(&benchmark.names)
+            .set_categories((overview.as_str(), STAGE_START_ROW, 0, last_stage_row, 0))
+            .set_values((sheet.name().as_str(), STAGE_START_ROW, col, last_stage_row, col));
+    }
+    insert(sheet, &mut cumulative)?;
+
+    // Pace per stage, straight off the overview
+    let mut pace = xls::Chart::new_column();
+    pace.title().set_name("Diff s/mi by stage");
+    pace.y_axis().set_name("s/mi");
+    for (i, benchmark) in comparison.benchmarks.iter().enumerate() {
+        let col = delta_column(i);
+        pace.add_series()
+            .set_name(&benchmark.names)
+            .set_categories((overview.as_str(), STAGE_START_ROW, 0, last_stage_row, 0))
+            .set_values((overview.as_str(), STAGE_START_ROW, col, last_stage_row, col));
+    }
+    insert(sheet, &mut pace)?;
+
+    // Sectors of each stage with splits. The split sheets put each sector in its own column,
+    // charts want them in a run of cells, so they're lined up here first.
+    let mut row = last_stage_row + 3;
+    for (stage_number, stage) in comparison.stages.iter().enumerate() {
+        if stage.split_distances.is_empty() {
+            continue
+        }
+        let splits = xls::utility::quote_sheet_name(&split_sheet_name(stage_number, stage));
+        sheet.write_with_format(row, 0, format!("SS{} {} sectors (s/mi)", stage_number + 1, stage.name), &formats.bold)?;
+        row += 1;
+        let heading_row = row;
+        sheet.write_with_format(heading_row, 0, "To mile", &formats.heading)?;
+        for (n, distance) in stage.split_distances.iter().enumerate() {
+            sheet.write_with_format(heading_row, 1 + n as u16, *distance, &formats.stage_length)?;
+        }
+        let last_col = stage.split_distances.len() as u16;
+
+        let mut sectors = xls::Chart::new_column();
+        sectors.title().set_name(&format!("SS{} {}", stage_number + 1, stage.name));
+        sectors.y_axis().set_name("s/mi");
+        for (i, (benchmark, bm_stage)) in comparison.benchmarks.iter().zip(stage.benchmarks.iter()).enumerate() {
+            row += 1;
+            sheet.write_with_format(row, 0, &benchmark.names, &formats.heading)?;
+            for (n, split_delta) in bm_stage.split_deltas.iter().enumerate() {
+                let Some(delta) = split_delta.sector else {
+                    continue
+                };
+                let cell = xls::utility::row_col_to_cell(split_layout::benchmark_row(i), split_layout::sector_delta_column(n));
+                sheet.write_formula_with_format(row, 1 + n as u16,
+                    xls::Formula::new(format!("{}!{}", splits, cell)).set_result(delta_value(delta).to_string()),
+                    &formats.delta)?;
+            }
+            sectors.add_series()
+                .set_name(&benchmark.names)
+                .set_categories((sheet.name().as_str(), heading_row, 1, heading_row, last_col))
+                .set_values((sheet.name().as_str(), row, 1, row, last_col));
+        }
+        insert(sheet, &mut sectors)?;
+        row += 2;
+    }
+
+    Ok(())
+}
+
+/// Everyone in the driver's field, one row each with the same columns for every stage
+pub fn build_class_field(comparison: &Comparison, sheet: &mut xls::Worksheet, options: WorkbookOptions) -> Result<(), Box<dyn Error>> {
+    let formats = format::get_formats(comparison.time_precision());
+    let overview = xls::utility::quote_sheet_name(&prepare_slug(&comparison.slug));
+
+    use class_field_layout::{delta_column, entry_row, position_column, stage_column, total_column, NAME_COLUMN, NUMBER_COLUMN};
+
+    sheet.set_column_width(NUMBER_COLUMN, 6)?;
+    sheet.set_column_width(NAME_COLUMN, 24)?;
+    sheet.write_with_format(0, 0, &comparison.rally, &formats.bold)?;
+    sheet.write_with_format(1, NUMBER_COLUMN, "Car", &formats.heading)?;
+    sheet.write_with_format(1, NAME_COLUMN, "Team", &formats.heading)?;
+
+    for (stage_number, stage) in comparison.stages.iter().enumerate() {
+        sheet.merge_range(0, stage_column(stage_number), 0, delta_column(stage_number),
+            &format!("SS{} {}", stage_number + 1, stage.name), &formats.driver_names)?;
+        sheet.write_with_format(1, stage_column(stage_number), "Time", &formats.heading)?;
+        sheet.write_with_format(1, total_column(stage_number), "Total", &formats.heading)?;
+        sheet.write_with_format(1, position_column(stage_number), "Pos (Class)", &formats.heading)?;
+        sheet.write_with_format(1, delta_column(stage_number), "Diff s/mi", &formats.heading)?;
+    }
+
+    let driver_row = comparison.class_field.iter()
+        .position(|e| e.competitor.number == comparison.driver.number)
+        .map(entry_row);
+    for (i, entry) in comparison.class_field.iter().enumerate() {
+        let row = entry_row(i);
+        let is_driver = Some(row) == driver_row;
+        let name_format = if is_driver { &formats.bold } else { &formats.stage_name };
+        sheet.write_with_format(row, NUMBER_COLUMN, entry.competitor.number as u32, name_format)?;
+        sheet.write_with_format(row, NAME_COLUMN, &entry.competitor.names, name_format)?;
+
+        for (stage_number, (stage, field_stage)) in comparison.stages.iter().zip(entry.stages.iter()).enumerate() {
+            let col = stage_column(stage_number);
+            write_stage(sheet, row, col, &field_stage.stage, format_time(&formats, &field_stage.stage, stage))?;
+            let Some(standing) = &field_stage.stage.position else {
+                continue
+            };
+            if standing.is_classified() {
+                write_time(sheet, row, total_column(stage_number), standing.total, &formats.stage_time)?;
+            }
+            if let Some(position) = position_text(standing) {
+                sheet.write_with_format(row, position_column(stage_number), position, &formats.position)?;
+            }
+            let formula = match driver_row {
+                Some(driver_row) if options.formulas && !is_driver && !(stage.driver.super_rally || field_stage.stage.super_rally) => {
+                    let driver = xls::utility::row_col_to_cell(driver_row, col);
+                    let entrant = xls::utility::row_col_to_cell(row, col);
+                    let length = xls::utility::row_col_to_cell(overview_layout::stage_row(stage_number), overview_layout::LENGTH_COLUMN);
+                    let length = format!("{}!{}", overview, length);
+                    Some(delta_formula(&driver, &entrant, &length, &[driver.clone(), entrant.clone()]))
+                },
+                _ => None,
+            };
+            write_delta(sheet, row, delta_column(stage_number), field_stage.delta, formula, &formats)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) const STAGE_HISTORY_SHEET: &str = "Stage History";
+pub(crate) const CLASS_FIELD_SHEET: &str = "Class Field";
+pub(crate) const CHARTS_SHEET: &str = "Charts";
+
 const BAD_CHARS: [char; 7] = ['[', ']',  ':', '*', '?', '/', '\\'];
-fn prepare_slug(name: &str) -> String {
-    let len = name.len();
-    let slug_len = ::std::cmp::min(len, 31);
-    let mut slug: String = name[..slug_len].into();
+pub(crate) fn prepare_slug(name: &str) -> String {
+    // Excel caps sheet names at 31 characters
+    let mut slug: String = name.chars().take(31).collect();
     for c in BAD_CHARS.iter() {
         slug = slug.replace(*c, "_");
     }
@@ -317,29 +849,296 @@ fn prepare_slug(name: &str) -> String {
     slug
 }
 
-pub fn build_spreadsheet(rally: &structures::Rally, uids: &UidMap, driver: usize, benchmarks: &[usize]) -> Result<xls::Workbook, Box<dyn Error>> {
-
-    let driver = rally.entries.iter()
-        .filter(|x| x.number == driver)
-        .next()
-        .ok_or_else(|| Box::new(SpreadSheetError::new(format!("Driver {} did not race in {}", driver, rally.title))))?;
-    let benchmarks: Vec<_> = rally.entries.iter().filter(|x| benchmarks.contains(&x.number)).collect();
+/// Name of the sheet holding the splits for a stage
+pub(crate) fn split_sheet_name(stage_number: usize, stage: &StageComparison) -> String {
+    prepare_slug(&format!("SS{} {}", stage_number+1, &stage.name))
+}
 
+pub fn build_workbook(comparison: &Comparison, options: WorkbookOptions) -> Result<xls::Workbook, Box<dyn Error>> {
     let mut workbook = Workbook::new();
-    let mut overview = workbook.add_worksheet();
-    let slug = prepare_slug(&rally.slug);
-    overview.set_name(slug)?;
-    build_overview(&rally, &uids, &driver, &benchmarks, &mut overview)?;
+    let overview = workbook.add_worksheet();
+    overview.set_name(prepare_slug(&comparison.slug))?;
+    build_overview(comparison, overview, options)?;
 
+    if !comparison.benchmarks.is_empty() && !comparison.stages.is_empty() {
+        let charts = workbook.add_worksheet();
+        charts.set_name(CHARTS_SHEET)?;
+        build_charts(comparison, charts)?;
+    }
 
-    for (stage_number, stage) in rally.stages.iter().enumerate() {
-        if !stage.has_splits() {
+    for (stage_number, stage) in comparison.stages.iter().enumerate() {
+        if stage.split_distances.is_empty() {
             continue
         }
-        let mut split_sheet = workbook.add_worksheet();
-        split_sheet.set_name(format!("SS{} {}", stage_number+1, &stage.name))?;
-        build_stage_with_splits(&rally, &uids, &driver, &benchmarks, &stage, stage_number, &mut split_sheet)?;
+        let split_sheet = workbook.add_worksheet();
+        split_sheet.set_name(split_sheet_name(stage_number, stage))?;
+        build_stage_with_splits(comparison, stage_number, split_sheet, options)?;
+    }
+
+    if !comparison.class_field.is_empty() {
+        let field_sheet = workbook.add_worksheet();
+        field_sheet.set_name(CLASS_FIELD_SHEET)?;
+        build_class_field(comparison, field_sheet, options)?;
+    }
+
+    if !comparison.stage_history.is_empty() {
+        let history_sheet = workbook.add_worksheet();
+        history_sheet.set_name(STAGE_HISTORY_SHEET)?;
+        build_stage_history(comparison, history_sheet)?;
+    }
+
+    Ok(workbook)
+}
+
+pub fn build_season(season: &Season, sheet: &mut xls::Worksheet) -> Result<(), Box<dyn Error>> {
+    let formats = format::get_formats(Precision::default());
+    let headings = ["Rally", "Car", "Rival Car", "Stages", "Diff s/mi", "Won", "Lost", "Tied"];
+
+    sheet.set_column_width(0, 36)?;
+    sheet.write_with_format(0, 0, format!("{} {}", season.driver, season.year), &formats.bold)?;
+
+    // Positive averages and trends mean the driver was faster or catching up
+    let signed_format = |value: f32| if value > 0.0 { &formats.delta_faster } else { &formats.delta };
+
+    let mut row = 2;
+    for rivalry in &season.rivals {
+        sheet.merge_range(row, 0, row, headings.len() as u16 - 1, &rivalry.name, &formats.driver_names)?;
+        row += 1;
+        for (col, heading) in headings.iter().enumerate() {
+            sheet.write_with_format(row, col as u16, *heading, &formats.heading)?;
+        }
+        row += 1;
+
+        let (mut won, mut lost, mut tied) = (0, 0, 0);
+        for rally in &rivalry.rallies {
+            sheet.write_with_format(row, 0, &rally.rally, &formats.stage_name)?;
+            sheet.write_with_format(row, 1, rally.driver_number as u32, &formats.stage_name)?;
+            sheet.write_with_format(row, 2, rally.rival_number as u32, &formats.stage_name)?;
+            sheet.write_with_format(row, 3, rally.stages as u32, &formats.stage_name)?;
+            if let Some(delta) = rally.delta {
+                sheet.write_with_format(row, 4, delta_value(delta), formats.delta(delta))?;
+            }
+            sheet.write_with_format(row, 5, rally.driver_wins as u32, &formats.stage_name)?;
+            sheet.write_with_format(row, 6, rally.rival_wins as u32, &formats.stage_name)?;
+            sheet.write_with_format(row, 7, rally.ties as u32, &formats.stage_name)?;
+            won += rally.driver_wins;
+            lost += rally.rival_wins;
+            tied += rally.ties;
+            row += 1;
+        }
+
+        sheet.write_with_format(row, 0, "Season", &formats.heading)?;
+        if let Some(average) = rivalry.average {
+            sheet.write_with_format(row, 4, average, signed_format(average))?;
+        }
+        sheet.write_with_format(row, 5, won as u32, &formats.bold)?;
+        sheet.write_with_format(row, 6, lost as u32, &formats.bold)?;
+        sheet.write_with_format(row, 7, tied as u32, &formats.bold)?;
+        row += 1;
+
+        sheet.write_with_format(row, 0, "Trend (s/mi per rally)", &formats.heading)?;
+        if let Some(trend) = rivalry.trend {
+            sheet.write_with_format(row, 4, trend, signed_format(trend))?;
+        }
+        row += 2;
+    }
+
+    Ok(())
+}
+
+pub fn build_season_workbook(season: &Season) -> Result<xls::Workbook, Box<dyn Error>> {
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+    sheet.set_name(prepare_slug(&format!("{} Season", season.year)))?;
+    build_season(season, sheet)?;
+    Ok(workbook)
+}
+
+pub fn build_championship_workbook(championship: &Championship) -> Result<xls::Workbook, Box<dyn Error>> {
+    let formats = format::get_formats(Precision::default());
+    let mut workbook = Workbook::new();
+
+    for table in &championship.tables {
+        let sheet = workbook.add_worksheet();
+        sheet.set_name(prepare_slug(&table.group))?;
+        sheet.set_column_width(1, 24)?;
+        sheet.write_with_format(0, 0, &table.group, &formats.bold)?;
+
+        let round_start_column = 2;
+        let total_column = round_start_column + table.rounds.len() as u16;
+        sheet.write_with_format(1, 0, "Pos", &formats.heading)?;
+        sheet.write_with_format(1, 1, "Driver", &formats.heading)?;
+        for (i, round) in table.rounds.iter().enumerate() {
+            sheet.write_with_format(1, round_start_column + i as u16, &round.title, &formats.heading)?;
+        }
+        sheet.write_with_format(1, total_column, "Total", &formats.heading)?;
+
+        for (row, standing) in table.standings.iter().enumerate() {
+            let row = row as u32 + 2;
+            sheet.write_with_format(row, 0, standing.position as u32, &formats.position)?;
+            sheet.write_with_format(row, 1, &standing.name, &formats.stage_name)?;
+            for (i, result) in standing.results.iter().enumerate() {
+                let Some(result) = result else {
+                    continue
+                };
+                // Dropped rounds are shaded like missing times
+                let format = if result.dropped { &formats.invalid_time } else { &formats.stage_time };
+                sheet.write_with_format(row, round_start_column + i as u16, result.points, format)?;
+            }
+            sheet.write_with_format(row, total_column, standing.total, &formats.bold)?;
+        }
     }
 
     Ok(workbook)
 }
+
+pub fn build_spreadsheet(rally: &structures::Rally, uids: &UidMap, driver: usize, benchmarks: &[usize], options: WorkbookOptions) -> Result<xls::Workbook, Box<dyn Error>> {
+    let comparison = Comparison::build(rally, uids, driver, benchmarks, None)?;
+    build_workbook(&comparison, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entry, rally, stage, uids, Xlsx};
+
+    fn penalised_rally() -> structures::Rally {
+        let mut penalised = entry(20, 3, "O2WD", "National", &["4:50.0", "2:55.0"]);
+        penalised["penalties"] = serde_json::json!([
+            {"control": "TC2", "stage": 1, "time": "10.0", "reason": "1 minute late"},
+        ]);
+        rally("penalties", vec![stage("Forest", 5.0), stage("Ridge", 3.0)], vec![
+            entry(10, 1, "O2WD", "National", &["5:00.0", "3:00.0"]),
+            penalised,
+        ])
+    }
+
+    #[test]
+    fn penalties_in_overview() {
+        let comparison = Comparison::build(&penalised_rally(), &uids(4), 20, &[10], None).unwrap();
+        let result = comparison.driver.result.as_ref().unwrap();
+        assert_eq!(result.unpenalised.to_string(), "07:45.0");
+        assert_eq!(result.penalties.to_string(), "10.0");
+        assert_eq!(result.total.to_string(), "07:55.0");
+
+        let mut xlsx = Xlsx::new(&mut build_workbook(&comparison, WorkbookOptions::default()).unwrap());
+        assert_eq!(xlsx.value(1, "D2").as_deref(), Some("Penalties"));
+        // Only on the stage they were given on
+        assert_eq!(xlsx.value(1, "D3"), None);
+        let ten_seconds = (10.0 / 86400.0_f64).to_string();
+        assert_eq!(xlsx.value(1, "D4"), Some(ten_seconds.clone()));
+        // Stage time, penalties and total under the stages
+        assert_eq!(xlsx.value(1, "A6").as_deref(), Some("Penalties"));
+        assert_eq!(xlsx.value(1, "C5"), Some((465.0 / 86400.0_f64).to_string()));
+        assert_eq!(xlsx.value(1, "C6"), Some(ten_seconds));
+        assert_eq!(xlsx.value(1, "C7"), Some((475.0 / 86400.0_f64).to_string()));
+        // Nothing for the benchmark who wasn't penalised
+        assert_eq!(xlsx.value(1, "F6"), Some("0".to_string()));
+    }
+
+    #[test]
+    fn times_and_deltas_are_numbers() {
+        let comparison = Comparison::build(&penalised_rally(), &uids(4), 20, &[10], None).unwrap();
+        let mut xlsx = Xlsx::new(&mut build_workbook(&comparison, WorkbookOptions::default()).unwrap());
+
+        // Driver's time on the first stage, then the benchmark's and the delta to it
+        for cell in ["C3", "F3", "G3"] {
+            let xml = xlsx.cell(1, cell).unwrap();
+            assert!(xml.contains("<v>") && !xml.contains(r#" t="s""#), "{cell} isn't a number: {xml}");
+        }
+        assert_eq!(xlsx.value(1, "C3"), Some((290.0 / 86400.0_f64).to_string()));
+        assert_eq!(xlsx.number_format(1, "C3").as_deref(), Some("[h]:mm:ss.0"));
+        assert_eq!(xlsx.number_format(1, "F3").as_deref(), Some("[h]:mm:ss.0"));
+        // The driver was 10 seconds faster over 5 miles
+        assert_eq!(xlsx.value(1, "G3").as_deref(), Some("2"));
+    }
+
+    /// Two stages, the second with a split halfway
+    fn split_rally() -> structures::Rally {
+        let mut ridge = stage("Ridge", 3.0);
+        ridge["splits"] = serde_json::json!([1.5]);
+        let mut driver = entry(10, 1, "O2WD", "National", &["5:00.0", "3:00.0"]);
+        driver["splits"] = serde_json::json!([[], ["1:30.0"]]);
+        let mut benchmark = entry(20, 3, "O2WD", "National", &["4:50.0", "2:55.0"]);
+        benchmark["splits"] = serde_json::json!([[], ["1:20.0"]]);
+        rally("splits", vec![stage("Forest", 5.0), ridge], vec![driver, benchmark])
+    }
+
+    #[test]
+    fn formulas() {
+        let comparison = Comparison::build(&split_rally(), &uids(4), 10, &[20], None).unwrap();
+        let mut xlsx = Xlsx::new(&mut build_workbook(&comparison, WorkbookOptions { formulas: true }).unwrap());
+        let formula = |xlsx: &mut Xlsx, sheet, cell| xlsx.formula(sheet, cell).unwrap_or_default();
+
+        // Overview: the benchmark's time less the driver's, over the stage length
+        assert_eq!(formula(&mut xlsx, 1, "G3"), r#"IF(AND(ISNUMBER(C3),ISNUMBER(F3)),(F3-(C3))*86400/(B3),"")"#);
+        assert_eq!(formula(&mut xlsx, 1, "G4"), r#"IF(AND(ISNUMBER(C4),ISNUMBER(F4)),(F4-(C4))*86400/(B4),"")"#);
+        // Stage times, the penalty column, then the two together
+        assert_eq!(formula(&mut xlsx, 1, "C5"), "SUM(C3:C4)");
+        assert_eq!(formula(&mut xlsx, 1, "C6"), "SUM(D3:D4)");
+        assert_eq!(formula(&mut xlsx, 1, "C7"), "C5+C6");
+        assert_eq!(formula(&mut xlsx, 1, "F5"), "SUM(F3:F4)");
+        assert_eq!(xlsx.formula(1, "F6"), None);
+        assert_eq!(formula(&mut xlsx, 1, "F7"), "F5+F6");
+
+        // The split sheet comes after the charts. Distances are on row 2, the driver on 3 and
+        // the benchmark on 4, with a split then its sector and cumulative deltas across.
+        assert_eq!(formula(&mut xlsx, 3, "E4"), r#"IF(AND(ISNUMBER(D3),ISNUMBER(D4)),(D4-(D3))*86400/(D2),"")"#);
+        assert_eq!(formula(&mut xlsx, 3, "F4"), r#"IF(AND(ISNUMBER(D3),ISNUMBER(D4)),(D4-(D3))*86400/(D2),"")"#);
+        assert_eq!(formula(&mut xlsx, 3, "H4"), r#"IF(AND(ISNUMBER(G3),ISNUMBER(G4),ISNUMBER(D3),ISNUMBER(D4)),(G4-D4-(G3-D3))*86400/(G2-D2),"")"#);
+        assert_eq!(formula(&mut xlsx, 3, "I4"), r#"IF(AND(ISNUMBER(G3),ISNUMBER(G4)),(G4-(G3))*86400/(G2),"")"#);
+    }
+
+    #[test]
+    fn charts() {
+        let comparison = Comparison::build(&split_rally(), &uids(4), 10, &[20], None).unwrap();
+        let mut xlsx = Xlsx::new(&mut build_workbook(&comparison, WorkbookOptions { formulas: true }).unwrap());
+        let range = |sheet: &str, first_row, first_col, last_row, last_col| {
+            xls::utility::worksheet_range_absolute(sheet, first_row, first_col, last_row, last_col)
+        };
+        let cell = |sheet: &str, row, col| format!("{}!{}", sheet, xls::utility::row_col_to_cell(row, col));
+        let first = overview_layout::STAGE_START_ROW;
+        let last = overview_layout::stage_row(1);
+        let stage_names = range("splits", first, 0, last, 0);
+
+        // Cumulative gap, pace per stage, and one for the only stage with splits
+        let charts = xlsx.chart_ranges();
+        assert_eq!(charts.len(), 3);
+        assert_eq!(charts[0], [stage_names.clone(), range("Charts", first, 1, last, 1)]);
+        assert_eq!(charts[1], [stage_names, range("splits", first, overview_layout::delta_column(0), last, overview_layout::delta_column(0))]);
+        assert_eq!(charts[2], ["Charts!$B$8:$C$8", "Charts!$B$9:$C$9"]);
+
+        // The gaps add up the overview times, the sectors come off the split sheet
+        let driver = cell("splits", last, overview_layout::DRIVER_COLUMN);
+        let benchmark = cell("splits", last, overview_layout::benchmark_column(0));
+        assert_eq!(xlsx.formula(2, "B4").unwrap(),
+            format!("B3+IF(AND(ISNUMBER({driver}),ISNUMBER({benchmark})),({benchmark}-{driver})*86400,0)"));
+        let split = (0..2).map(|n| cell("'SS2 Ridge'", split_layout::benchmark_row(0), split_layout::sector_delta_column(n)));
+        assert_eq!([xlsx.formula(2, "B9").unwrap(), xlsx.formula(2, "C9").unwrap()], split.collect::<Vec<_>>()[..]);
+    }
+
+    #[test]
+    fn class_field() {
+        let rally = rally("field", vec![stage("Forest", 5.0), stage("Ridge", 3.0)], vec![
+            entry(10, 1, "O2WD", "National", &["5:00.0", "3:00.0"]),
+            entry(20, 3, "O2WD", "National", &["4:50.0", "2:55.0"]),
+            entry(30, 5, "R5", "National", &["4:40.0", "2:50.0"]),
+            entry(40, 7, "O2WD", "National", &["5:10.0", "3:10.0"]),
+        ]);
+        let mut comparison = Comparison::build(&rally, &uids(8), 10, &[20], None).unwrap();
+        comparison.add_class_field(&rally, &uids(8), crate::comparison::FieldScope::Class);
+        let mut xlsx = Xlsx::new(&mut build_workbook(&comparison, WorkbookOptions { formulas: true }).unwrap());
+
+        // After the overview and charts, the O2WD cars in the order they finished
+        let numbers: Vec<_> = ["A3", "A4", "A5", "A6"].iter().map(|cell| xlsx.value(3, cell)).collect();
+        assert_eq!(numbers, [Some("20".to_string()), Some("10".to_string()), Some("40".to_string()), None]);
+        assert_eq!(xlsx.value(3, "I3").as_deref(), Some("2 (1)"));
+        assert_eq!(xlsx.value(3, "I5").as_deref(), Some("4 (3)"));
+
+        // Each entrant against the driver on row 4, over the lengths on the overview
+        assert_eq!(xlsx.formula(3, "F3").unwrap(), r#"IF(AND(ISNUMBER(C4),ISNUMBER(C3)),(C3-(C4))*86400/(field!B3),"")"#);
+        assert_eq!(xlsx.formula(3, "J5").unwrap(), r#"IF(AND(ISNUMBER(G4),ISNUMBER(G5)),(G5-(G4))*86400/(field!B4),"")"#);
+        assert_eq!(xlsx.formula(3, "F4"), None);
+        assert_eq!(xlsx.value(1, "B4").as_deref(), Some("3"));
+    }
+}
@@ -0,0 +1,251 @@
+use serde::Serialize;
+
+use crate::structures::{self, Precision, StageTime};
+
+/// Where a single entry stands after a given stage.
+#[derive(Serialize, Clone, Debug)]
+pub struct Standing {
+    pub number: usize,
+    /// Sum of every valid stage time and penalty up to and including this stage
+    pub total: StageTime,
+    /// Sum of stage times alone, before any penalties
+    pub unpenalised: StageTime,
+    pub penalties: StageTime,
+    /// Positions are only set for entries that have a time on every stage that has been run so far
+    pub overall: Option<usize>,
+    pub class: Option<usize>,
+    pub category: Option<usize>,
+    pub gap_to_leader: Option<StageTime>,
+    pub gap_to_ahead: Option<StageTime>,
+    pub retired: bool,
+}
+
+impl Standing {
+    pub fn is_classified(&self) -> bool {
+        self.overall.is_some()
+    }
+
+    /// The same standing with every time rounded to `precision`, positions are left as they were
+    pub fn with_precision(&self, precision: Precision) -> Self {
+        Standing {
+            total: self.total.with_precision(precision),
+            unpenalised: self.unpenalised.with_precision(precision),
+            penalties: self.penalties.with_precision(precision),
+            gap_to_leader: self.gap_to_leader.map(|t| t.with_precision(precision)),
+            gap_to_ahead: self.gap_to_ahead.map(|t| t.with_precision(precision)),
+            ..self.clone()
+        }
+    }
+}
+
+/// The whole field after a stage, classified entries first in overall order.
+#[derive(Serialize, Clone, Debug)]
+pub struct StageStandings {
+    pub stage: usize,
+    pub standings: Vec<Standing>,
+}
+
+impl StageStandings {
+    pub fn for_entry(&self, number: usize) -> Option<&Standing> {
+        self.standings.iter().find(|s| s.number == number)
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Standings {
+    pub stages: Vec<StageStandings>,
+}
+
+impl Standings {
+    pub fn compute(rally: &structures::Rally) -> Self {
+        let mut stages = vec![];
+        let mut totals: Vec<StageTime> = vec![StageTime::zero(); rally.entries.len()];
+        let mut penalties: Vec<StageTime> = vec![StageTime::zero(); rally.entries.len()];
+        let mut missed: Vec<bool> = vec![false; rally.entries.len()];
+
+        for stage in 0..rally.stages.len() {
+            // A stage nobody has a time on was cancelled or hasn't been run yet, and shouldn't
+            // count against anyone
+            let was_run = rally.entries.iter()
+                .any(|e| stage_time(e, stage).is_some());
+
+            for (i, entry) in rally.entries.iter().enumerate() {
+                if let Some(time) = stage_time(entry, stage) {
+                    totals[i] = totals[i] + time;
+                } else if was_run {
+                    missed[i] = true;
+                }
+                penalties[i] = penalties[i] + entry.penalties_on(stage);
+            }
+
+            let mut classified: Vec<usize> = (0..rally.entries.len())
+                .filter(|i| !missed[*i])
+                .collect();
+            classified.sort_by_key(|i| totals[*i] + penalties[*i]);
+
+            let mut standings = vec![];
+            for (position, i) in classified.iter().enumerate() {
+                let entry = &rally.entries[*i];
+                let class = classified[..position].iter()
+                    .filter(|j| rally.entries[**j].class == entry.class)
+                    .count();
+                let category = classified[..position].iter()
+                    .filter(|j| rally.entries[**j].category == entry.category)
+                    .count();
+                let total = totals[*i] + penalties[*i];
+                let (gap_to_leader, gap_to_ahead) = if position == 0 {
+                    (None, None)
+                } else {
+                    let leader = classified[0];
+                    let ahead = classified[position - 1];
+                    (Some(total - (totals[leader] + penalties[leader])),
+                     Some(total - (totals[ahead] + penalties[ahead])))
+                };
+
+                standings.push(Standing {
+                    number: entry.number,
+                    total,
+                    unpenalised: totals[*i],
+                    penalties: penalties[*i],
+                    overall: Some(position + 1),
+                    class: Some(class + 1),
+                    category: Some(category + 1),
+                    gap_to_leader,
+                    gap_to_ahead,
+                    retired: false,
+                });
+            }
+
+            for (i, entry) in rally.entries.iter().enumerate() {
+                if !missed[i] {
+                    continue
+                }
+                standings.push(Standing {
+                    number: entry.number,
+                    total: totals[i] + penalties[i],
+                    unpenalised: totals[i],
+                    penalties: penalties[i],
+                    overall: None,
+                    class: None,
+                    category: None,
+                    gap_to_leader: None,
+                    gap_to_ahead: None,
+                    retired: entry.retired_on().is_some_and(|s| s <= stage),
+                });
+            }
+
+            stages.push(StageStandings {
+                stage,
+                standings,
+            });
+        }
+
+        Standings {
+            stages,
+        }
+    }
+
+    pub fn after_stage(&self, stage: usize) -> Option<&StageStandings> {
+        self.stages.get(stage)
+    }
+
+    pub fn for_entry(&self, stage: usize, number: usize) -> Option<&Standing> {
+        self.after_stage(stage)?.for_entry(number)
+    }
+
+    pub fn final_standings(&self) -> Option<&StageStandings> {
+        self.stages.last()
+    }
+}
+
+/// Super Rally times count towards the total like any other
+fn stage_time(entry: &structures::Entry, stage: usize) -> Option<StageTime> {
+    entry.times.get(stage).and_then(|r| r.recorded())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entry, rally, stage};
+
+    fn standing(standings: &Standings, stage: usize, number: usize) -> &Standing {
+        standings.for_entry(stage, number).unwrap()
+    }
+
+    #[test]
+    fn class_and_category_positions() {
+        let rally = rally("positions", vec![stage("Forest", 5.0)], vec![
+            entry(1, 1, "O2WD", "National", &["5:00.0"]),
+            entry(2, 3, "L2WD", "National", &["5:10.0"]),
+            entry(3, 5, "O2WD", "Regional", &["5:20.0"]),
+        ]);
+        let standings = Standings::compute(&rally);
+        let positions = |number| {
+            let s = standing(&standings, 0, number);
+            (s.overall, s.class, s.category)
+        };
+        assert_eq!(positions(1), (Some(1), Some(1), Some(1)));
+        assert_eq!(positions(2), (Some(2), Some(1), Some(2)));
+        assert_eq!(positions(3), (Some(3), Some(2), Some(1)));
+    }
+
+    #[test]
+    fn gaps() {
+        let rally = rally("gaps", vec![stage("Forest", 5.0), stage("Ridge", 3.0)], vec![
+            entry(1, 1, "O2WD", "National", &["5:00.0", "3:00.0"]),
+            entry(2, 3, "O2WD", "National", &["5:10.0", "2:55.0"]),
+            entry(3, 5, "O2WD", "National", &["5:20.0", "3:00.5"]),
+        ]);
+        let standings = Standings::compute(&rally);
+        let gaps = |number| {
+            let s = standing(&standings, 1, number);
+            (s.gap_to_leader.map(|t| t.to_string()), s.gap_to_ahead.map(|t| t.to_string()))
+        };
+        assert_eq!(gaps(1), (None, None));
+        assert_eq!(gaps(2), (Some("05.0".to_string()), Some("05.0".to_string())));
+        assert_eq!(gaps(3), (Some("20.5".to_string()), Some("15.5".to_string())));
+    }
+
+    #[test]
+    fn missed_stage_declassifies() {
+        let mut retired = entry(3, 5, "O2WD", "National", &["4:50.0", ""]);
+        retired["retirements"] = serde_json::json!([
+            {"status": "Permanent", "control": "TC2", "stage": 1, "reason": "mechanical"},
+        ]);
+        let rally = rally("missed", vec![stage("Forest", 5.0), stage("Ridge", 3.0)], vec![
+            entry(1, 1, "O2WD", "National", &["5:00.0", "3:00.0"]),
+            entry(2, 3, "O2WD", "National", &["5:10.0", ""]),
+            retired,
+        ]);
+        let standings = Standings::compute(&rally);
+
+        // Leading until they stopped
+        assert_eq!(standing(&standings, 0, 3).overall, Some(1));
+        for number in [2, 3] {
+            let s = standing(&standings, 1, number);
+            assert!(!s.is_classified());
+            assert_eq!((s.class, s.category, s.gap_to_leader), (None, None, None));
+        }
+        assert!(!standing(&standings, 1, 2).retired);
+        assert!(standing(&standings, 1, 3).retired);
+        assert_eq!(standing(&standings, 1, 1).overall, Some(1));
+        // Classified entries come first
+        let order: Vec<_> = standings.stages[1].standings.iter().map(|s| s.number).collect();
+        assert_eq!(order[0], 1);
+    }
+
+    #[test]
+    fn cancelled_stage_does_not_count() {
+        let rally = rally("cancelled", vec![stage("Forest", 5.0), stage("Flooded", 2.0), stage("Ridge", 3.0)], vec![
+            entry(1, 1, "O2WD", "National", &["5:00.0", "", "3:00.0"]),
+            entry(2, 3, "O2WD", "National", &["5:10.0", "", "2:55.0"]),
+        ]);
+        let standings = Standings::compute(&rally);
+        for stage in 0..3 {
+            assert!(standings.stages[stage].standings.iter().all(|s| s.is_classified()));
+        }
+        let leader = standing(&standings, 2, 1);
+        assert_eq!(leader.total.to_string(), "08:00.0");
+        assert_eq!(standing(&standings, 2, 2).gap_to_leader.unwrap().to_string(), "05.0");
+    }
+}
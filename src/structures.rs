@@ -1,110 +1,377 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize, Serializer};
 use std::time;
 use std::sync::OnceLock;
 use regex::Regex;
 use serde::de::{self, Visitor, Deserializer};
 use std::fmt;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use chrono::NaiveDate;
 
 pub type UidMap = HashMap::<usize, Uid>;
 
-struct StageTimeRegexes {
-    hoursre: Regex,
-    minutesre: Regex,
-    secondsre: Regex,
-    nosecondsre: Regex,
-}
-
-fn parse_stage_time<'a>(time: &'a str) -> Option<StageTime> {
-    static REGEX: OnceLock<StageTimeRegexes> = OnceLock::new();
-    let regexes = REGEX.get_or_init(|| {
-        StageTimeRegexes {
-            hoursre: Regex::new(r"^(\d+):(\d+):(\d+\.\d+)$").unwrap(),
-            minutesre: Regex::new(r"^(\d+):(\d+\.\d+)$").unwrap(),
-            secondsre: Regex::new(r"^(\d+\.\d+)$").unwrap(),
-            nosecondsre: Regex::new(r"^(\d+):(\d+)$").unwrap(),
-        }
-    });
-
-    // We really want to make this be an option but that has.. annoying type implications
-    if time == "" {
-        return Some(StageTime { time: time::Duration::new(0, 0) })
-    }
-
-    for (_, [hours, minutes, seconds]) in regexes.hoursre.captures_iter(time).map(|c| c.extract()) {
-        let hours: u64 = hours.parse().unwrap();
-        let minutes: u64 = minutes.parse().unwrap();
-        let seconds: f32 = seconds.parse().unwrap();
-        // Make sure the tenths are correct
-        let millis = (seconds.fract() * 10f32).round() as u32;
-        return Some(StageTime { time: time::Duration::new(
-            (hours * 60 * 60) +
-            (minutes * 60) +
-            seconds as u64,
-            millis * 1_000_000_00
-        ) } )
-    }
-    for (_, [minutes, seconds]) in regexes.minutesre.captures_iter(time).map(|c| c.extract()) {
-        let minutes: u64 = minutes.parse().unwrap();
-        let seconds: f32 = seconds.parse().unwrap();
-        // Make sure the tenths are correct
-        let millis = (seconds.fract() * 10f32).round() as u32;
-        return Some(StageTime { time: time::Duration::new(
-            (minutes * 60) +
-            seconds as u64,
-            millis * 1_000_000_00
-        ) } )
-    }
-    for (_, [seconds]) in regexes.secondsre.captures_iter(time).map(|c| c.extract()) {
-        let seconds: f32 = seconds.parse().unwrap();
-        // Make sure the tenths are correct
-        let millis = (seconds.fract() * 10f32).round() as u32;
-        return Some(StageTime { time: time::Duration::new(
-            seconds as u64,
-            millis * 1_000_000_00
-        ) } )
-    }
-    for (_, [minutes, seconds]) in regexes.nosecondsre.captures_iter(time).map(|c| c.extract()) {
-        let minutes: u64 = minutes.parse().unwrap();
-        let seconds: f32 = seconds.parse().unwrap();
-        return Some(StageTime { time: time::Duration::new(
-            (minutes * 60) +
-            seconds as u64,
-            0
-        ) } )
-    }
-    return None
+fn parse_stage_time(time: &str) -> Option<StageTime> {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    // [[hours:]minutes:]seconds[.fraction]
+    let re = REGEX.get_or_init(|| { Regex::new(r"^(?:(?:(\d+):)?(\d+):)?(\d+)(?:\.(\d+))?$").unwrap() });
+
+    let captures = re.captures(time)?;
+    let number = |i| captures.get(i).map_or(Ok(0), |m| m.as_str().parse::<u64>()).ok();
+    let (hours, minutes, seconds) = (number(1)?, number(2)?, number(3)?);
+
+    // Parsed as digits rather than a float so nothing is lost to rounding
+    let fraction = captures.get(4).map_or("", |m| m.as_str());
+    let precision = Precision::from_digits(fraction.len());
+    let mut millis: u32 = format!("{:0<3}", &fraction[..fraction.len().min(3)]).parse().ok()?;
+    // Past thousandths is more than a Duration of millis holds, round it off
+    if fraction.as_bytes().get(3).is_some_and(|d| *d >= b'5') {
+        millis += 1;
+    }
+
+    Some(StageTime {
+        time: time::Duration::from_secs(hours * 60 * 60 + minutes * 60 + seconds)
+            + time::Duration::from_millis(millis.into()),
+        precision,
+    })
 }
 
-#[derive(Deserialize, Clone)]
-pub struct Rally {
+/// Upstream dates are mostly plain dates, but some older ones are timestamps. Only the date is
+/// kept, anything that doesn't start with one is None.
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok()
+}
+
+/// Where a rally is relative to a given day
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RallyStatus {
+    Upcoming,
+    InProgress,
+    Finished,
+}
+
+/// A rally as upstream publishes it, before stage results are worked out across the field
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct RallyData {
     source: String,
     startDate: String,
     finishDate: String,
+    title: String,
+    slug: String,
+    entries: Vec<Entry>,
+    stages: Vec<Stage>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(from = "RallyData")]
+#[allow(non_snake_case)]
+pub struct Rally {
+    /// Where upstream got the results from
+    pub source: String,
+    pub startDate: Option<NaiveDate>,
+    pub finishDate: Option<NaiveDate>,
     pub title: String,
     pub slug: String,
     pub entries: Vec<Entry>,
     pub stages: Vec<Stage>,
 }
 
+impl From<RallyData> for Rally {
+    fn from(data: RallyData) -> Self {
+        let mut rally = Rally {
+            source: data.source,
+            startDate: parse_date(&data.startDate),
+            finishDate: parse_date(&data.finishDate),
+            title: data.title,
+            slug: data.slug,
+            entries: data.entries,
+            stages: data.stages,
+        };
+
+        // Nobody having a time could just mean the stage hasn't been run yet, it's only
+        // cancelled if a later one has been
+        let has_time = |rally: &Rally, stage: usize| rally.entries.iter()
+            .any(|e| e.times.get(stage).is_some_and(|r| r.recorded().is_some()));
+        let last_run = (0..rally.stages.len()).rev().find(|stage| has_time(&rally, *stage));
+        for stage in 0..last_run.unwrap_or(0) {
+            if has_time(&rally, stage) {
+                continue
+            }
+            for entry in rally.entries.iter_mut() {
+                if let Some(result @ StageResult::NotRun) = entry.times.get_mut(stage) {
+                    *result = StageResult::Cancelled;
+                }
+            }
+        }
+        rally
+    }
+}
+
 impl Rally {
-    pub fn entry_by_driver_number<'a>(&'a self, number: usize) -> Option<&'a Entry> {
-        self.entries.iter().filter(|i| i.number == number).next()
+    /// None if upstream didn't give any dates. A rally with only one runs on just that day.
+    pub fn status(&self, today: NaiveDate) -> Option<RallyStatus> {
+        let start = self.startDate.or(self.finishDate)?;
+        let finish = self.finishDate.unwrap_or(start);
+        Some(if today < start {
+            RallyStatus::Upcoming
+        } else if today > finish {
+            RallyStatus::Finished
+        } else {
+            RallyStatus::InProgress
+        })
+    }
+
+    /// Whether any day of the rally is between `from` and `to`, inclusive. Rallies without
+    /// dates are only included when neither is given.
+    pub fn within(&self, from: Option<NaiveDate>, to: Option<NaiveDate>) -> bool {
+        if from.is_none() && to.is_none() {
+            return true;
+        }
+        let Some(start) = self.startDate.or(self.finishDate) else {
+            return false
+        };
+        let finish = self.finishDate.unwrap_or(start);
+        from.is_none_or(|from| finish >= from) && to.is_none_or(|to| start <= to)
+    }
+
+    pub fn entry_by_driver_number(&self, number: usize) -> Option<&Entry> {
+        self.entries.iter().find(|i| i.number == number)
+    }
+
+    pub fn entry_by_driver_uid(&self, uid: usize) -> Option<&Entry> {
+        self.entries.iter().find(|i| i.driverUID == uid)
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        let haystack = format!("{} {}", self.slug, self.title).to_lowercase();
+        query_words(query)
+            .all(|word| haystack.contains(&word))
+    }
+
+    /// Find entrants by car number, or failing that by driver or codriver name. Every word of
+    /// the query has to match part of a name, allowing for a typo in longer words. If anyone's
+    /// name matches exactly only they are returned, so "Smith" doesn't also pick out "Smithson".
+    pub fn find_entry(&self, uids: &UidMap, query: &str) -> Vec<&Entry> {
+        if let Ok(number) = query.trim().parse() {
+            return self.entry_by_driver_number(number).into_iter().collect();
+        }
+        if query_words(query).next().is_none() {
+            return vec![];
+        }
+
+        let candidates: Vec<_> = self.entries.iter()
+            .filter_map(|entry| entry.name_match(uids, query).map(|exact| (entry, exact)))
+            .collect();
+        let exact: Vec<_> = candidates.iter().filter(|(_, exact)| *exact).map(|(entry, _)| *entry).collect();
+        if !exact.is_empty() {
+            return exact;
+        }
+        candidates.into_iter().map(|(entry, _)| entry).collect()
+    }
+
+    /// Pairs of stage indexes, ours then theirs, that look like the same stage. A stage run
+    /// twice matches both passes.
+    pub fn shared_stages(&self, other: &Rally) -> Vec<(usize, usize)> {
+        let mut shared = vec![];
+        for (i, ours) in self.stages.iter().enumerate() {
+            for (j, theirs) in other.stages.iter().enumerate() {
+                if ours.is_same_stage(theirs) {
+                    shared.push((i, j));
+                }
+            }
+        }
+        shared
+    }
+
+    /// Stages both entries drove and have a time on
+    pub fn comparable_stages(&self, a: &Entry, b: &Entry) -> Vec<usize> {
+        (0..self.stages.len())
+            .filter(|i| a.time_on(*i).is_some() && b.time_on(*i).is_some())
+            .collect()
+    }
+
+    /// Seconds per mile between `a` and `b` over all the stages they can be compared on
+    pub fn pace_delta(&self, a: &Entry, b: &Entry) -> Option<Delta> {
+        let mut a_total = StageTime::zero();
+        let mut b_total = StageTime::zero();
+        let mut miles = 0.0;
+        for i in self.comparable_stages(a, b) {
+            if let (Some(a_time), Some(b_time)) = (a.time_on(i), b.time_on(i)) {
+                a_total = a_total + a_time;
+                b_total = b_total + b_time;
+                miles += self.stages[i].length;
+            }
+        }
+
+        (miles > 0.0).then(|| a_total.diff_per_mile(&b_total, miles))
+    }
+}
+
+/// Find someone who drove in any of `rallies` by uid, or failing that by name. Car numbers
+/// change between rallies so they aren't any use here.
+pub fn find_driver<'a>(rallies: &[Rally], uids: &'a UidMap, query: &str) -> Vec<&'a Uid> {
+    let drivers = rallies.iter()
+        .flat_map(|r| r.entries.iter().map(|e| e.driverUID));
+    find_uid(uids, drivers, query)
+}
+
+/// Find one of `candidates` by uid, or failing that by name. Exact name matches win as in
+/// Rally::find_entry.
+pub fn find_uid<'a>(uids: &'a UidMap, candidates: impl IntoIterator<Item = usize>, query: &str) -> Vec<&'a Uid> {
+    let mut candidates: Vec<usize> = candidates.into_iter().collect();
+    candidates.sort();
+    candidates.dedup();
+    let people = candidates.iter().filter_map(|uid| uids.get(uid));
+
+    if let Ok(uid) = query.trim().parse::<usize>() {
+        return people.filter(|d| d.uid == uid).collect();
+    }
+    if query_words(query).next().is_none() {
+        return vec![];
+    }
+
+    let matches: Vec<_> = people
+        .filter_map(|d| d.name_match(query).map(|exact| (d, exact)))
+        .collect();
+    let exact: Vec<_> = matches.iter().filter(|(_, exact)| *exact).map(|(d, _)| *d).collect();
+    if !exact.is_empty() {
+        return exact;
+    }
+    matches.into_iter().map(|(d, _)| d).collect()
+}
+
+fn query_words(query: &str) -> impl Iterator<Item = String> + '_ {
+    query.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+}
+
+/// Oldest first. Rallies without dates go after the rest, in the order they were given.
+pub fn sort_chronologically(rallies: &mut [Rally]) {
+    rallies.sort_by_key(|r| (r.startDate.is_none(), r.startDate, r.finishDate));
+}
+
+/// Find rallies by slug, or failing that by every word of the query appearing in the slug or
+/// title. An exact slug match always wins, otherwise every candidate is returned.
+pub fn find_rally<'a>(rallies: &'a [Rally], query: &str) -> Vec<&'a Rally> {
+    if let Some(rally) = rallies.iter().find(|r| r.slug == query) {
+        return vec![rally];
+    }
+    rallies.iter().filter(|r| r.matches(query)).collect()
+}
+
+/// Upstream spellings of categories and classes that aren't the names we use for them, kept in
+/// a file rather than here so a new one doesn't need a release. Anything that's neither is kept
+/// as it was published, in the Other variants.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct Aliases {
+    #[serde(default)]
+    pub categories: HashMap<String, Category>,
+    #[serde(default)]
+    pub classes: HashMap<String, Class>,
+}
+
+impl Aliases {
+    /// Swap anything published under an alias for the name we use
+    pub fn apply(&self, rally: &mut Rally) {
+        for entry in &mut rally.entries {
+            if let Some(category) = self.categories.get(entry.category.name()) {
+                entry.category = category.clone();
+            }
+            if let Some(class) = self.classes.get(entry.class.name()) {
+                entry.class = class.clone();
+            }
+        }
     }
 }
 
-#[derive(Deserialize, Eq, PartialEq, Clone)]
+#[derive(Deserialize, Serialize, Eq, PartialEq, Clone, Debug)]
+#[serde(from = "String", into = "String")]
 pub enum Category {
     National,
     Regional,
     RallySprint,
-     #[serde(rename(deserialize = "Rally Ready RallySprint"))]
     RallyReadyRallySprint,
-     #[serde(rename(deserialize = "ARA RallySprint"))]
     AraRallySprint,
     Exhibition,
+    /// Anything we don't know about yet, as published
+    Other(String),
+}
+
+impl Category {
+    const KNOWN: &[Category] = &[
+        Category::National,
+        Category::Regional,
+        Category::RallySprint,
+        Category::RallyReadyRallySprint,
+        Category::AraRallySprint,
+        Category::Exhibition,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Category::National => "National",
+            Category::Regional => "Regional",
+            Category::RallySprint => "RallySprint",
+            Category::RallyReadyRallySprint => "RallyReadyRallySprint",
+            Category::AraRallySprint => "AraRallySprint",
+            Category::Exhibition => "Exhibition",
+            Category::Other(name) => name,
+        }
+    }
+
+    /// Sprints are one day events over a handful of stages rather than full rallies
+    pub fn is_sprint(&self) -> bool {
+        matches!(self, Category::RallySprint | Category::RallyReadyRallySprint | Category::AraRallySprint)
+    }
 }
-#[derive(Deserialize, Copy, Clone, PartialEq)]
+
+impl From<&str> for Category {
+    fn from(name: &str) -> Self {
+        let name = name.trim();
+        Category::KNOWN.iter()
+            .find(|c| c.name() == name)
+            .cloned()
+            .unwrap_or_else(|| Category::Other(name.to_string()))
+    }
+}
+
+impl From<String> for Category {
+    fn from(name: String) -> Self {
+        Category::from(name.as_str())
+    }
+}
+
+impl From<Category> for String {
+    fn from(category: Category) -> Self {
+        category.name().to_string()
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// Which wheels are driven, for the classes that say
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Drivetrain {
+    #[serde(rename = "2WD")]
+    TwoWheel,
+    #[serde(rename = "4WD")]
+    FourWheel,
+}
+
+/// Whether a class is open or limited in what can be done to the car
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tier {
+    Open,
+    Limited,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Debug)]
+#[serde(from = "String", into = "String")]
 pub enum Class {
     O4WD,
     L4WD,
@@ -112,12 +379,83 @@ pub enum Class {
     L2WD,
     RC2,
     NA4WD,
-    #[serde(rename(deserialize = "Class-X"))]
-    #[serde(rename(deserialize = "Class X"))]
     ClassX,
+    /// Anything we don't know about yet, as published
+    Other(String),
+}
+
+impl Class {
+    const KNOWN: &[Class] = &[
+        Class::O4WD,
+        Class::L4WD,
+        Class::O2WD,
+        Class::L2WD,
+        Class::RC2,
+        Class::NA4WD,
+        Class::ClassX,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Class::O4WD => "O4WD",
+            Class::L4WD => "L4WD",
+            Class::O2WD => "O2WD",
+            Class::L2WD => "L2WD",
+            Class::RC2 => "RC2",
+            Class::NA4WD => "NA4WD",
+            Class::ClassX => "ClassX",
+            Class::Other(name) => name,
+        }
+    }
+
+    /// None for classes that mix them, or that we don't know about
+    pub fn drivetrain(&self) -> Option<Drivetrain> {
+        match self {
+            Class::O4WD | Class::L4WD | Class::RC2 | Class::NA4WD => Some(Drivetrain::FourWheel),
+            Class::O2WD | Class::L2WD => Some(Drivetrain::TwoWheel),
+            Class::ClassX | Class::Other(_) => None,
+        }
+    }
+
+    /// None for classes that are neither, or that we don't know about
+    pub fn tier(&self) -> Option<Tier> {
+        match self {
+            Class::O4WD | Class::O2WD => Some(Tier::Open),
+            Class::L4WD | Class::L2WD => Some(Tier::Limited),
+            Class::RC2 | Class::NA4WD | Class::ClassX | Class::Other(_) => None,
+        }
+    }
 }
 
-#[derive(Deserialize, Clone, PartialEq)]
+impl From<&str> for Class {
+    fn from(name: &str) -> Self {
+        let name = name.trim();
+        Class::KNOWN.iter()
+            .find(|c| c.name() == name)
+            .cloned()
+            .unwrap_or_else(|| Class::Other(name.to_string()))
+    }
+}
+
+impl From<String> for Class {
+    fn from(name: String) -> Self {
+        Class::from(name.as_str())
+    }
+}
+
+impl From<Class> for String {
+    fn from(class: Class) -> Self {
+        class.name().to_string()
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
 pub enum BoxColor {
     // TODO(richo) Yeah I dunno what this is honestly.
     #[serde(rename(deserialize = ""))]
@@ -126,36 +464,192 @@ pub enum BoxColor {
     Red,
 }
 
-#[derive(Deserialize, Clone)]
-struct Penalty {
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Penalty {
+    pub control: String,
+    pub stage: usize,
+    #[serde(rename(deserialize = "time"))]
+    pub amount: StageTime,
+    pub reason: String,
 }
 
-#[derive(Deserialize, Clone)]
-enum RetirementStatus {
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum RetirementStatus {
     Permanent,
     Temporary,
     Rejoined,
 }
 
-#[derive(Deserialize, Clone)]
-struct Retirement {
-    control: String,
-    stage: usize,
-    status: RetirementStatus,
-    reason: String,
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Retirement {
+    pub control: String,
+    pub stage: usize,
+    pub status: RetirementStatus,
+    pub reason: String,
+}
+
+/// What an entry got on a stage
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(tag = "kind", content = "time")]
+pub enum StageResult {
+    /// Driven and timed
+    Time(StageTime),
+    /// No time yet, either the stage hasn't been run or they haven't come through
+    NotRun,
+    /// No time because they were out of the rally, for good or for now
+    Retired,
+    /// Given a time under Super Rally rather than driving it, so it counts towards the total but
+    /// can't be compared with anyone
+    Nominal(StageTime),
+    /// Nobody got a time, though later stages were run
+    Cancelled,
+}
+
+impl StageResult {
+    /// The time they drove, if they did
+    pub fn time(&self) -> Option<StageTime> {
+        match self {
+            StageResult::Time(time) => Some(*time),
+            _ => None,
+        }
+    }
+
+    /// The time that counts towards their total, nominal or not
+    pub fn recorded(&self) -> Option<StageTime> {
+        match self {
+            StageResult::Time(time) | StageResult::Nominal(time) => Some(*time),
+            _ => None,
+        }
+    }
+
+    /// The same result with the time changed, anything without one stays as it is
+    pub fn map(self, f: impl FnOnce(StageTime) -> StageTime) -> Self {
+        match self {
+            StageResult::Time(time) => StageResult::Time(f(time)),
+            StageResult::Nominal(time) => StageResult::Nominal(f(time)),
+            other => other,
+        }
+    }
+
+    /// Seconds per mile between two driven times, nothing else can be compared
+    pub fn diff_per_mile(&self, other: &Self, distance: f32) -> Option<Delta> {
+        match (self, other) {
+            (StageResult::Time(a), StageResult::Time(b)) => Some(a.diff_per_mile(b, distance)),
+            _ => None,
+        }
+    }
+}
+
+/// How an entry stood on a given stage
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum StageStatus {
+    Running,
+    /// Out of the rally for now, possibly to rejoin under Super Rally
+    RetiredTemporary,
+    /// Back in after a temporary retirement, running under Super Rally
+    Rejoined,
+    Retired,
+}
+
+impl StageStatus {
+    pub fn is_running(&self) -> bool {
+        matches!(self, StageStatus::Running)
+    }
+
+    /// Out for now or back in after it, either way not driving for a competitive time
+    pub fn is_super_rally(&self) -> bool {
+        matches!(self, StageStatus::RetiredTemporary | StageStatus::Rejoined)
+    }
+}
+
+/// How finely a time was published, which is how finely it gets printed back out
+#[derive(Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    Seconds,
+    #[default]
+    Tenths,
+    Hundredths,
+    Thousandths,
 }
 
-#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug)]
+impl Precision {
+    /// Anything past thousandths is kept as thousandths
+    fn from_digits(digits: usize) -> Self {
+        match digits {
+            0 => Precision::Seconds,
+            1 => Precision::Tenths,
+            2 => Precision::Hundredths,
+            _ => Precision::Thousandths,
+        }
+    }
+
+    /// Digits after the decimal point
+    pub fn digits(&self) -> usize {
+        match self {
+            Precision::Seconds => 0,
+            Precision::Tenths => 1,
+            Precision::Hundredths => 2,
+            Precision::Thousandths => 3,
+        }
+    }
+
+    /// Milliseconds in the smallest step this precision can show
+    fn step(&self) -> u128 {
+        10u128.pow(3 - self.digits() as u32)
+    }
+}
+
+impl std::str::FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "seconds" => Ok(Precision::Seconds),
+            "tenths" => Ok(Precision::Tenths),
+            "hundredths" => Ok(Precision::Hundredths),
+            "thousandths" => Ok(Precision::Thousandths),
+            _ => Err(format!("Expected seconds, tenths, hundredths or thousandths, not {}", s)),
+        }
+    }
+}
+
+/// A time along with the precision it was given to. Times are equal, and order, by value alone,
+/// so 3:00.0 and 3:00.00 are the same time.
+#[derive(Clone, Copy, Debug)]
 pub struct StageTime {
     time: time::Duration,
+    precision: Precision,
 }
 
+impl PartialEq for StageTime {
+    fn eq(&self, other: &Self) -> bool {
+        self.time == other.time
+    }
+}
+
+impl Eq for StageTime {}
+
+impl PartialOrd for StageTime {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for StageTime {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.time.cmp(&other.time)
+    }
+}
+
+// Sums and differences are as precise as the finer of the two
 impl std::ops::Add for StageTime {
     type Output = Self;
 
     fn add(self, other: Self) -> Self {
         Self {
             time: self.time + other.time,
+            precision: self.precision.max(other.precision),
         }
     }
 }
@@ -166,65 +660,84 @@ impl std::ops::Sub for StageTime {
     fn sub(self, other: Self) -> Self {
         Self {
             time: self.time - other.time,
+            precision: self.precision.max(other.precision),
         }
     }
 }
 
 impl StageTime {
-    pub fn is_valid(&self) -> bool {
-        !self.time.is_zero()
-    }
-
     pub fn diff_per_mile(&self, other: &Self, distance: f32) -> Delta {
-        if ! (self.is_valid() && other.is_valid()) {
-            return Delta::invalid();
-        }
-
         if self > other {
-            return Delta {
+            Delta {
                 delta: (self.time - other.time).as_secs_f32() / distance,
                 kind: DeltaKind::Slower,
             }
         } else if other > self {
-            return Delta {
+            Delta {
                 delta: (other.time - self.time).as_secs_f32() / distance,
                 kind: DeltaKind::Faster,
             }
+        } else {
+            Delta::equal()
         }
-        return Delta::equal();
     }
 
+    /// As coarse as possible, so summing from zero is only as precise as what's summed
     pub fn zero() -> Self {
         Self {
             time: std::time::Duration::ZERO,
+            precision: Precision::Seconds,
+        }
+    }
+
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
+
+    /// The time rounded to the nearest step of `precision`, half up. Asking for a finer
+    /// precision than the time was given to only changes how it prints.
+    pub fn with_precision(&self, precision: Precision) -> Self {
+        let step = precision.step();
+        let millis = (self.time.as_millis() + step / 2) / step * step;
+        Self {
+            time: time::Duration::from_millis(millis as u64),
+            precision,
         }
     }
+
+    pub fn as_secs_f64(&self) -> f64 {
+        self.time.as_secs_f64()
+    }
 }
 
+#[derive(Serialize, Clone, Copy, Debug)]
 pub enum DeltaKind {
     Faster,
     Slower,
     Equal,
-    Invalid,
 }
 
+#[derive(Serialize, Clone, Copy, Debug)]
 pub struct Delta {
     pub delta: f32,
     pub kind: DeltaKind,
 }
 
 impl Delta {
-    fn invalid() -> Self {
+    fn equal() -> Self {
         Delta {
             delta: 0.0,
-            kind: DeltaKind::Invalid,
+            kind: DeltaKind::Equal,
         }
     }
+}
 
-    fn equal() -> Self {
-        Delta {
-            delta: 0.0,
-            kind: DeltaKind::Equal,
+impl Delta {
+    /// The delta with the sign it's displayed with, negative when slower
+    pub fn signed(&self) -> f32 {
+        match self.kind {
+            DeltaKind::Slower => -self.delta,
+            _ => self.delta,
         }
     }
 }
@@ -232,19 +745,17 @@ impl Delta {
 impl fmt::Display for Delta {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let sign = match self.kind {
-            DeltaKind::Invalid |
-                DeltaKind::Equal |
+            DeltaKind::Equal |
                 DeltaKind::Faster => "",
             DeltaKind::Slower => "-",
         };
-        return write!(f, "{}{:.02}", sign, self.delta)
+        write!(f, "{}{:.02}", sign, self.delta)
     }
 }
 
 impl fmt::Display for StageTime {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let secs = self.time.as_secs();
-        let millis = self.time.subsec_millis() ;
 
         let hours = secs / 3600;
         let secs = secs % 3600;
@@ -253,12 +764,19 @@ impl fmt::Display for StageTime {
         let secs = secs % 60;
 
         if hours > 0 {
-            return write!(f, "{}:{:02}:{:02}.{:02}", hours, mins, secs, millis);
+            write!(f, "{}:{:02}:{:02}", hours, mins, secs)?;
+        } else if mins > 0 {
+            write!(f, "{:02}:{:02}", mins, secs)?;
+        } else {
+            write!(f, "{:02}", secs)?;
         }
-        if mins > 0 {
-            return write!(f, "{:02}:{:02}.{:02}", mins, secs, millis);
+
+        let digits = self.precision.digits();
+        if digits > 0 {
+            let fraction = self.time.subsec_millis() as u128 / self.precision.step();
+            write!(f, ".{:0digits$}", fraction)?;
         }
-        write!(f, "{:02}.{:02}", secs, millis)
+        Ok(())
     }
 }
 
@@ -267,14 +785,23 @@ struct StageTimeVisitor;
 impl<'de> Visitor<'de> for StageTimeVisitor {
     type Value = StageTime;
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("a stage time formatted as 0:00:00.0")
+        formatter.write_str("a stage time formatted as 0:00:00.0, to any precision down to thousandths")
     }
 
     fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
     where
         E: de::Error,
     {
-        parse_stage_time(value).ok_or(E::custom("Invalid time"))
+        parse_stage_time(value).ok_or_else(|| E::custom(format!("invalid stage time {:?}", value)))
+    }
+}
+
+impl Serialize for StageTime {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
     }
 }
 
@@ -288,38 +815,187 @@ impl<'de> Deserialize<'de> for StageTime {
 }
 
 
-#[derive(Deserialize, Clone)]
+/// An entry as upstream publishes it, where a stage without a time is an empty string
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct EntryData {
+        category: Category,
+        number: usize,
+        driverUID: usize,
+        codriverUID: usize,
+        carClass: Class,
+        carModel: String,
+        times: Vec<PublishedTime>,
+        colors: Vec<BoxColor>,
+        penalties: Vec<Penalty>,
+        retirements: Vec<Retirement>,
+        splits: Option<Vec<Vec<PublishedTime>>>,
+}
+
+/// A time from a list of them, where upstream leaves an empty string for one that's missing
+struct PublishedTime(Option<StageTime>);
+
+impl<'de> Deserialize<'de> for PublishedTime {
+    fn deserialize<D>(deserializer: D) -> Result<PublishedTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let time = String::deserialize(deserializer)?;
+        if time.is_empty() {
+            return Ok(PublishedTime(None));
+        }
+        StageTimeVisitor.visit_str(&time).map(|time| PublishedTime(Some(time)))
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(from = "EntryData")]
+#[allow(non_snake_case)]
 pub struct Entry {
         pub category: Category,
         pub number: usize,
         pub driverUID: usize,
         pub codriverUID: usize,
-        #[serde(rename(deserialize = "carClass"))]
         pub class: Class,
-        #[serde(rename(deserialize = "carModel"))]
-        model: String,
-        pub times: Vec<StageTime>,
+        pub model: String,
+        pub times: Vec<StageResult>,
         pub colors: Vec<BoxColor>,
-        penalties: Vec<Penalty>,
+        pub penalties: Vec<Penalty>,
         retirements: Vec<Retirement>,
-        pub splits: Option<Vec<Vec<StageTime>>>,
+        /// Cumulative times at each split, None where one wasn't recorded
+        pub splits: Option<Vec<Vec<Option<StageTime>>>>,
+}
+
+impl From<EntryData> for Entry {
+    fn from(data: EntryData) -> Self {
+        let mut entry = Entry {
+            category: data.category,
+            number: data.number,
+            driverUID: data.driverUID,
+            codriverUID: data.codriverUID,
+            class: data.carClass,
+            model: data.carModel,
+            times: vec![],
+            colors: data.colors,
+            penalties: data.penalties,
+            retirements: data.retirements,
+            splits: data.splits.map(|splits| splits.into_iter()
+                .map(|stage| stage.into_iter().map(|split| split.0).collect())
+                .collect()),
+        };
+        entry.times = data.times.iter().enumerate()
+            .map(|(stage, time)| match (time.0, entry.is_super_rally(stage)) {
+                (Some(time), true) => StageResult::Nominal(time),
+                (Some(time), false) => StageResult::Time(time),
+                (None, _) if entry.stage_status(stage).is_running() => StageResult::NotRun,
+                (None, _) => StageResult::Retired,
+            })
+            .collect();
+        entry
+    }
 }
 
 impl Entry {
-    pub fn driver<'a>(&self, map: &'a UidMap) -> &'a Uid {
-        &map[&self.driverUID]
+    /// The time they drove on a stage, if they did
+    pub fn time_on(&self, stage: usize) -> Option<StageTime> {
+        self.times.get(stage).and_then(|r| r.time())
+    }
+
+    /// None if the uids file doesn't know them, which can happen for a while after a refresh
+    pub fn driver<'a>(&self, map: &'a UidMap) -> Option<&'a Uid> {
+        map.get(&self.driverUID)
     }
 
-    pub fn codriver<'a>(&self, map: &'a UidMap) -> &'a Uid {
-        &map[&self.codriverUID]
+    pub fn codriver<'a>(&self, map: &'a UidMap) -> Option<&'a Uid> {
+        map.get(&self.codriverUID)
+    }
+
+    pub fn driver_name(&self, map: &UidMap) -> String {
+        full_name(map, self.driverUID)
+    }
+
+    pub fn codriver_name(&self, map: &UidMap) -> String {
+        full_name(map, self.codriverUID)
     }
 
     pub fn names(&self, map: &UidMap) -> String {
-        format!("{}/{}", self.driver(&map).l, self.codriver(&map).l)
+        let last_name = |uid: Option<&Uid>| uid.map_or(UNKNOWN_NAME, |u| u.last_name()).to_string();
+        format!("{}/{}", last_name(self.driver(map)), last_name(self.codriver(map)))
+    }
+
+    /// None if the query doesn't match the driver or codriver, otherwise whether every word
+    /// matched a whole name exactly
+    fn name_match(&self, map: &UidMap, query: &str) -> Option<bool> {
+        [self.driverUID, self.codriverUID].iter()
+            .filter_map(|uid| map.get(uid))
+            .filter_map(|uid| uid.name_match(query))
+            .reduce(|a, b| a || b)
+    }
+
+    /// Total penalty time applied on this stage
+    pub fn penalties_on(&self, stage: usize) -> StageTime {
+        self.penalties.iter()
+            .filter(|p| p.stage == stage)
+            .fold(StageTime::zero(), |acc, p| acc + p.amount)
+    }
+
+    /// Total penalty time applied over the whole rally
+    pub fn total_penalties(&self) -> StageTime {
+        self.penalties.iter()
+            .fold(StageTime::zero(), |acc, p| acc + p.amount)
+    }
+
+    pub fn retirements(&self) -> &[Retirement] {
+        &self.retirements
+    }
+
+    /// The earliest stage this entry permanently retired on, if it did
+    pub fn retired_on(&self) -> Option<usize> {
+        self.retirements.iter()
+            .filter(|r| r.status == RetirementStatus::Permanent)
+            .map(|r| r.stage)
+            .min()
+    }
+
+    /// The retirement in effect on this stage, if any. A permanent retirement always wins over
+    /// an earlier temporary one.
+    pub fn retirement_on(&self, stage: usize) -> Option<&Retirement> {
+        let mut current: Option<&Retirement> = None;
+        for retirement in self.retirements.iter().filter(|r| r.stage <= stage) {
+            current = match current {
+                Some(c) if c.status == RetirementStatus::Permanent => Some(c),
+                Some(c) if c.stage > retirement.stage => Some(c),
+                _ => Some(retirement),
+            };
+        }
+        current
+    }
+
+    pub fn stage_status(&self, stage: usize) -> StageStatus {
+        match self.retirement_on(stage) {
+            None => StageStatus::Running,
+            Some(r) => match r.status {
+                RetirementStatus::Permanent => StageStatus::Retired,
+                RetirementStatus::Temporary => StageStatus::RetiredTemporary,
+                // The stage they went out on is still covered by Super Rally
+                RetirementStatus::Rejoined if r.stage == stage => StageStatus::RetiredTemporary,
+                RetirementStatus::Rejoined => StageStatus::Rejoined,
+            },
+        }
+    }
+
+    pub fn stage_statuses(&self) -> Vec<StageStatus> {
+        (0..self.times.len()).map(|i| self.stage_status(i)).collect()
+    }
+
+    /// Whether this stage was run, or notionally timed, under Super Rally rules
+    pub fn is_super_rally(&self, stage: usize) -> bool {
+        self.colors.get(stage) == Some(&BoxColor::Red) ||
+            self.stage_status(stage).is_super_rally()
     }
 
     /// This is the cumulative time to this split
-    pub fn splits_with_finish(&self) -> Vec<Vec<StageTime>> {
+    pub fn splits_with_finish(&self) -> Vec<Vec<Option<StageTime>>> {
         // TODO(richo) There's some clever way to do this with once and chain but I'm tired
         let mut splits = self.splits.clone().unwrap_or_else(|| {
             let mut vec = vec![];
@@ -328,34 +1004,39 @@ impl Entry {
             };
             vec
         });
-        for (splits, time) in splits.iter_mut().zip(self.times.iter()) {
-            splits.push(*time);
+        for (splits, result) in splits.iter_mut().zip(self.times.iter()) {
+            splits.push(result.recorded());
         }
         splits
     }
 
     /// The sector time in this split
-    pub fn sectors_with_finish(&self) -> Vec<Vec<StageTime>> {
-        let mut sectors = self.splits_with_finish();
-        println!("{:?}", &sectors);
-        for stage in sectors.iter_mut() {
-            let mut prev_time = StageTime::zero();
-            for time in stage.iter_mut() {
-                if ! time.is_valid() {
-                    continue
-                }
-                let elapsed = *time - prev_time;
-                *time = elapsed;
-                prev_time = elapsed;
-            }
+    pub fn sectors_with_finish(&self) -> Vec<Vec<Option<StageTime>>> {
+        self.splits_with_finish().iter().map(|stage| sectors(stage)).collect()
+    }
+
+    /// Cumulative time at each of the `count` splits on a stage, the last being the finish, so
+    /// they line up with Stage::splits_with_finish however many splits were published
+    pub fn splits_on(&self, stage: usize, count: usize) -> Vec<Option<StageTime>> {
+        if count == 0 {
+            return vec![];
         }
+        let published = self.splits.as_ref().and_then(|s| s.get(stage));
+        let mut splits: Vec<_> = (0..count - 1)
+            .map(|n| published.and_then(|p| p.get(n).copied().flatten()))
+            .collect();
+        splits.push(self.times.get(stage).and_then(|r| r.recorded()));
+        splits
+    }
 
-        sectors
+    /// Time for each sector between the splits of splits_on
+    pub fn sectors_on(&self, stage: usize, count: usize) -> Vec<Option<StageTime>> {
+        sectors(&self.splits_on(stage, count))
     }
 }
 
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Stage {
     pub name: String,
     pub length: f32,
@@ -365,20 +1046,60 @@ pub struct Stage {
 impl Stage {
     pub fn has_splits(&self) -> bool {
         match &self.splits {
-            Some(n) => n.len() > 0,
+            Some(n) => !n.is_empty(),
             None => false,
         }
     }
 
     pub fn splits_with_finish(&self) -> Vec<f32> {
         // TODO(richo) There's some clever way to do this with once and chain but I'm tired
-        let mut splits = self.splits.clone().unwrap_or_else(Vec::new);
+        let mut splits = self.splits.clone().unwrap_or_default();
         splits.push(self.length);
         splits
     }
+
+    /// Whether this looks like the same piece of road as `other`, most likely from another
+    /// year, and the lengths have to agree. Names drift ("Maryhill" becomes "Maryhill Forest")
+    /// so one name can have words the other doesn't, as long as none of them say it's run a
+    /// different way ("Red" and "Red Reverse" are different stages).
+    pub fn is_same_stage(&self, other: &Stage) -> bool {
+        let longest = self.length.max(other.length);
+        if longest <= 0.0 || (self.length - other.length).abs() / longest > STAGE_LENGTH_TOLERANCE {
+            return false;
+        }
+        let ours: HashSet<_> = query_words(&self.name).collect();
+        let theirs: HashSet<_> = query_words(&other.name).collect();
+        if ours.is_empty() || theirs.is_empty() || !(ours.is_subset(&theirs) || theirs.is_subset(&ours)) {
+            return false;
+        }
+        !ours.symmetric_difference(&theirs).any(|w| STAGE_VARIANT_WORDS.contains(&w.as_str()))
+    }
 }
 
+/// The time between each cumulative split and the one before, None unless both are there
+fn sectors(splits: &[Option<StageTime>]) -> Vec<Option<StageTime>> {
+    let mut prev_time = Some(StageTime::zero());
+    splits.iter().map(|split| {
+        let sector = match (prev_time, *split) {
+            // Out of order splits are bad data, not a negative sector
+            (Some(prev), Some(split)) if split > prev => Some(split - prev),
+            _ => None,
+        };
+        prev_time = *split;
+        sector
+    }).collect()
+}
+
+/// Words that make a stage a different run over the same roads rather than a renaming
+const STAGE_VARIANT_WORDS: &[&str] = &["reverse", "long", "short", "up", "down"];
+
+/// How far apart, as a fraction of the longer one, two stage lengths can be and still be the
+/// same stage. Remeasuring moves things a little from year to year.
+const STAGE_LENGTH_TOLERANCE: f32 = 0.05;
+
+// Deliberately not Serialize, this has contact details in it
 #[derive(Deserialize, Clone)]
+#[allow(dead_code)]
 pub struct Uid {
     pub uid: usize,
     f: String,
@@ -393,6 +1114,17 @@ pub struct Uid {
     email: Option<String>,
 }
 
+/// Shown in place of anyone the uids file doesn't know about
+pub const UNKNOWN_NAME: &str = "Unknown";
+
+/// First and last name, or UNKNOWN_NAME and the uid if there's no such uid
+pub fn full_name(map: &UidMap, uid: usize) -> String {
+    match map.get(&uid) {
+        Some(person) => format!("{} {}", person.first_name(), person.last_name()),
+        None => format!("{} ({})", UNKNOWN_NAME, uid),
+    }
+}
+
 impl Uid {
     pub fn first_name(&self) -> &str {
         &self.f
@@ -401,4 +1133,129 @@ impl Uid {
     pub fn last_name(&self) -> &str {
         &self.l
     }
+
+    fn name_match(&self, query: &str) -> Option<bool> {
+        let names: Vec<_> = query_words(&format!("{} {}", self.f, self.l)).collect();
+        let mut exact = true;
+        for word in query_words(query) {
+            if names.contains(&word) {
+                continue
+            }
+            exact = false;
+            let close = |name: &String| {
+                name.contains(&word) || (word.len() >= 4 && strsim::osa_distance(name, &word) <= 1)
+            };
+            if !names.iter().any(close) {
+                return None;
+            }
+        }
+        Some(exact)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use serde_json::json;
+
+    fn stage(name: &str, length: f32) -> Stage {
+        Stage { name: name.to_string(), length, splits: None }
+    }
+
+    fn time(time: &str) -> StageTime {
+        serde_json::from_value(json!(time)).unwrap()
+    }
+
+    #[test]
+    fn stage_time_round_trip() {
+        for published in ["1:02:03", "02:03", "03", "02:03.4", "02:03.45", "02:03.456", "1:02:03.40"] {
+            assert_eq!(time(published).to_string(), published);
+            assert_eq!(serde_json::to_value(time(published)).unwrap(), published);
+        }
+        assert_eq!(time("02:03.45").precision(), Precision::Hundredths);
+        assert_eq!(time("1:02:03").precision(), Precision::Seconds);
+        assert_eq!(time("1:02:03"), time("62:03.000"));
+    }
+
+    #[test]
+    fn stage_time_past_thousandths() {
+        assert_eq!(time("02:03.4564").to_string(), "02:03.456");
+        assert_eq!(time("02:03.4565").to_string(), "02:03.457");
+        assert_eq!(time("59.99951").to_string(), "01:00.000");
+        assert_eq!(time("02:03.4565").precision(), Precision::Thousandths);
+    }
+
+    #[test]
+    fn stage_time_sums() {
+        let total = [time("01:02"), time("01:03")].into_iter().fold(StageTime::zero(), |acc, t| acc + t);
+        assert_eq!(total.to_string(), "02:05");
+        let total = total + time("00.25");
+        assert_eq!(total.to_string(), "02:05.25");
+        assert_eq!(time("02:05.25").with_precision(Precision::Tenths).to_string(), "02:05.3");
+    }
+
+    #[test]
+    fn missing_splits() {
+        let mut split = testing::stage("Split", 2.0);
+        split["splits"] = json!([1.0]);
+        let mut entry = testing::entry(10, 0, "O4WD", "National", &["02:00.0", "", "01:00.0"]);
+        entry["splits"] = json!([[""], ["00:30.0"], ["01:10.0"]]);
+        let rally = testing::rally("splits", vec![split.clone(), split.clone(), split], vec![entry]);
+        let entry = &rally.entries[0];
+
+        let show = |stages: Vec<Vec<Option<StageTime>>>| -> Vec<Vec<String>> {
+            stages.iter()
+                .map(|s| s.iter().map(|t| t.map(|t| t.to_string()).unwrap_or_default()).collect())
+                .collect()
+        };
+        assert_eq!(show(entry.splits_with_finish()), vec![
+            vec!["", "02:00.0"],
+            vec!["30.0", ""],
+            vec!["01:10.0", "01:00.0"],
+        ]);
+        // A sector needs both ends, and an end before its start is bad data
+        assert_eq!(show(entry.sectors_with_finish()), vec![
+            vec!["", ""],
+            vec!["30.0", ""],
+            vec!["01:10.0", ""],
+        ]);
+    }
+
+    #[test]
+    fn super_rally_stages() {
+        let mut rejoined = testing::entry(10, 0, "O4WD", "National", &["01:00.0", "", "01:30.0", "01:00.0"]);
+        rejoined["retirements"] = json!([{"status": "Rejoined", "control": "TC2", "stage": 1, "reason": "off"}]);
+        let mut red = testing::entry(20, 2, "O4WD", "National", &["01:00.0", "01:00.0", "01:00.0", "01:00.0"]);
+        red["colors"] = json!(["", "", "red", ""]);
+        let stages = (0..4).map(|i| testing::stage(&format!("SS{i}"), 1.0)).collect();
+        let rally = testing::rally("super_rally", stages, vec![rejoined, red]);
+
+        let rejoined = &rally.entries[0];
+        assert_eq!(rejoined.stage_statuses(), vec![
+            StageStatus::Running, StageStatus::RetiredTemporary, StageStatus::Rejoined, StageStatus::Rejoined,
+        ]);
+        // Back in, but still not competing for the rest of the rally
+        assert_eq!((0..4).map(|s| rejoined.is_super_rally(s)).collect::<Vec<_>>(), vec![false, true, true, true]);
+        assert_eq!(rejoined.times[2], StageResult::Nominal(time("01:30.0")));
+        assert_eq!(rejoined.times[1], StageResult::Retired);
+        let red = &rally.entries[1];
+        assert_eq!((0..4).map(|s| red.is_super_rally(s)).collect::<Vec<_>>(), vec![false, false, true, false]);
+    }
+
+    #[test]
+    fn same_stage() {
+        let red = stage("Red", 5.0);
+        assert!(red.is_same_stage(&stage("red", 5.1)));
+        assert!(stage("Maryhill", 3.0).is_same_stage(&stage("Maryhill Forest", 3.0)));
+        assert!(stage("Maryhill Forest", 3.0).is_same_stage(&stage("Maryhill", 3.0)));
+        // Too far apart to be a remeasure
+        assert!(!red.is_same_stage(&stage("Red", 6.0)));
+        // The same roads run another way
+        assert!(!red.is_same_stage(&stage("Red Reverse", 5.0)));
+        assert!(!red.is_same_stage(&stage("Long Red Reverse", 5.0)));
+        assert!(!stage("Red Reverse", 5.0).is_same_stage(&stage("Long Red Reverse", 5.0)));
+        assert!(!stage("Maryhill", 3.0).is_same_stage(&stage("Maryhill Up", 3.0)));
+        assert!(!red.is_same_stage(&stage("Blue", 5.0)));
+    }
 }
@@ -0,0 +1,135 @@
+use serde::Serialize;
+
+use crate::standings::Standings;
+use crate::structures;
+
+/// How many of the closest cars on pace to suggest
+const SIMILAR_PACE: usize = 2;
+
+/// Why a car was suggested as a benchmark
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum Reason {
+    OverallLeader,
+    ClassLeader,
+    AheadInClass,
+    BehindInClass,
+    SimilarPace,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Suggestion {
+    pub number: usize,
+    /// A car can be suggested for more than one reason, e.g. leading both overall and in class
+    pub reasons: Vec<Reason>,
+}
+
+/// Suggest benchmarks for a driver: the overall and class leaders, the cars either side of them
+/// in class, and whoever was closest on pace over the stages they both ran. The driver is never
+/// suggested, and each car only appears once. Empty if the driver didn't race in the rally.
+pub fn suggest_benchmarks(rally: &structures::Rally, driver: usize) -> Vec<Suggestion> {
+    let Some(entry) = rally.entry_by_driver_number(driver) else {
+        return vec![];
+    };
+
+    let mut suggestions: Vec<Suggestion> = vec![];
+    let mut suggest = |number: usize, reason: Reason| {
+        if number == driver {
+            return;
+        }
+        match suggestions.iter_mut().find(|s| s.number == number) {
+            Some(suggestion) => suggestion.reasons.push(reason),
+            None => suggestions.push(Suggestion { number, reasons: vec![reason] }),
+        }
+    };
+
+    let in_class = |number: &usize| rally.entry_by_driver_number(*number)
+        .is_some_and(|e| e.class == entry.class);
+
+    let standings = Standings::compute(rally);
+    if let Some(last) = standings.final_standings() {
+        let classified: Vec<_> = last.standings.iter()
+            .filter(|s| s.is_classified())
+            .map(|s| s.number)
+            .collect();
+        if let Some(leader) = classified.first() {
+            suggest(*leader, Reason::OverallLeader);
+        }
+        if let Some(leader) = classified.iter().find(|n| in_class(n)) {
+            suggest(*leader, Reason::ClassLeader);
+        }
+    }
+
+    // Drivers who retired are compared with who was around them when they went out
+    let last_classified = standings.stages.iter().rev()
+        .find(|s| s.for_entry(driver).is_some_and(|s| s.is_classified()));
+    if let Some(stage) = last_classified {
+        let class: Vec<_> = stage.standings.iter()
+            .filter(|s| s.is_classified())
+            .map(|s| s.number)
+            .filter(in_class)
+            .collect();
+        let position = class.iter().position(|n| *n == driver).unwrap();
+        if position > 0 {
+            suggest(class[position - 1], Reason::AheadInClass);
+        }
+        if let Some(behind) = class.get(position + 1) {
+            suggest(*behind, Reason::BehindInClass);
+        }
+    }
+
+    let mut paces: Vec<_> = rally.entries.iter()
+        .filter(|other| other.number != driver)
+        .filter_map(|other| rally.pace_delta(entry, other).map(|pace| (other.number, pace.delta)))
+        .collect();
+    paces.sort_by(|a, b| a.1.total_cmp(&b.1));
+    for (number, _) in paces.into_iter().take(SIMILAR_PACE) {
+        suggest(number, Reason::SimilarPace);
+    }
+
+    suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entry, rally, stage};
+
+    fn suggested(rally: &structures::Rally, driver: usize) -> Vec<(usize, Vec<Reason>)> {
+        suggest_benchmarks(rally, driver).into_iter().map(|s| (s.number, s.reasons)).collect()
+    }
+
+    #[test]
+    fn leaders_neighbours_and_pace() {
+        let rally = rally("suggest", vec![stage("Forest", 5.0)], vec![
+            entry(1, 1, "L2WD", "National", &["4:50.0"]),
+            entry(6, 11, "L2WD", "National", &["5:10.0"]),
+            entry(2, 3, "O2WD", "National", &["5:00.0"]),
+            entry(3, 5, "O2WD", "National", &["5:05.0"]),
+            entry(4, 7, "O2WD", "National", &["5:10.0"]),
+            entry(5, 9, "O2WD", "National", &["6:00.0"]),
+        ]);
+        use Reason::*;
+        // 6, 2 and 4 are all a second a mile away, ties go to whoever's listed first
+        assert_eq!(suggested(&rally, 3), vec![
+            (1, vec![OverallLeader]),
+            (2, vec![ClassLeader, AheadInClass, SimilarPace]),
+            (4, vec![BehindInClass]),
+            (6, vec![SimilarPace]),
+        ]);
+        // Leading the class themselves, so only the car behind is a neighbour
+        assert_eq!(suggested(&rally, 2), vec![
+            (1, vec![OverallLeader, SimilarPace]),
+            (3, vec![BehindInClass, SimilarPace]),
+        ]);
+    }
+
+    #[test]
+    fn empty_field() {
+        let rally = rally("alone", vec![stage("Forest", 5.0)], vec![
+            entry(1, 1, "O2WD", "National", &["5:00.0"]),
+        ]);
+        assert!(suggested(&rally, 1).is_empty());
+        // Not in the rally at all
+        assert!(suggested(&rally, 2).is_empty());
+    }
+}
@@ -0,0 +1,117 @@
+//! Builders for unit tests, producing data the same shape upstream publishes it so it goes
+//! through the same deserialization as the real files
+
+use regex::Regex;
+use serde_json::{json, Value};
+use std::io::{Cursor, Read};
+
+use crate::structures::{Rally, Uid, UidMap};
+
+pub(crate) fn stage(name: &str, length: f32) -> Value {
+    json!({"name": name, "length": length})
+}
+
+/// An entry with no penalties or retirements, the codriver is always `uid + 1`. Anything else
+/// can be added to the returned value.
+pub(crate) fn entry(number: usize, uid: usize, class: &str, category: &str, times: &[&str]) -> Value {
+    json!({
+        "category": category, "number": number, "driverUID": uid, "codriverUID": uid + 1,
+        "carClass": class, "carModel": "Test Car",
+        "times": times, "colors": vec![""; times.len()],
+        "penalties": [], "retirements": [],
+    })
+}
+
+/// A rally as it would appear in a data file
+pub(crate) fn rally_data(slug: &str, stages: Vec<Value>, entries: Vec<Value>) -> Value {
+    json!({
+        "slug": slug, "source": "none", "title": slug,
+        "startDate": "2025-05-16", "finishDate": "2025-05-17",
+        "stages": stages, "entries": entries,
+    })
+}
+
+pub(crate) fn rally(slug: &str, stages: Vec<Value>, entries: Vec<Value>) -> Rally {
+    serde_json::from_value(rally_data(slug, stages, entries)).unwrap()
+}
+
+/// Everyone from uid 0 up to `last`, named after their uid
+pub(crate) fn uids(last: usize) -> UidMap {
+    (0..=last)
+        .map(|uid| {
+            let person: Uid = serde_json::from_value(json!({"uid": uid, "f": "Person", "l": uid.to_string()})).unwrap();
+            (uid, person)
+        })
+        .collect()
+}
+
+/// A saved workbook, for checking what actually ended up in the cells
+pub(crate) struct Xlsx(zip::ZipArchive<Cursor<Vec<u8>>>);
+
+impl Xlsx {
+    pub(crate) fn new(workbook: &mut rust_xlsxwriter::Workbook) -> Self {
+        Xlsx(zip::ZipArchive::new(Cursor::new(workbook.save_to_buffer().unwrap())).unwrap())
+    }
+
+    pub(crate) fn part(&mut self, name: &str) -> String {
+        let mut part = String::new();
+        self.0.by_name(name).unwrap().read_to_string(&mut part).unwrap();
+        part
+    }
+
+    /// The `<c>` element for a cell like "D4" on a sheet, counting from 1, if anything was written
+    pub(crate) fn cell(&mut self, sheet: usize, cell: &str) -> Option<String> {
+        let sheet = self.part(&format!("xl/worksheets/sheet{sheet}.xml"));
+        let re = Regex::new(&format!(r#"<c r="{cell}"[^>]*?(/>|>.*?</c>)"#)).unwrap();
+        re.find(&sheet).map(|m| m.as_str().to_string())
+    }
+
+    /// What a cell holds: the shared string for string cells, otherwise the raw `<v>`
+    pub(crate) fn value(&mut self, sheet: usize, cell: &str) -> Option<String> {
+        let cell = self.cell(sheet, cell)?;
+        let value = Regex::new(r"<v>(.*?)</v>").unwrap().captures(&cell)?[1].to_string();
+        if !cell.contains(r#" t="s""#) {
+            return Some(value);
+        }
+        let strings = self.part("xl/sharedStrings.xml");
+        let strings: Vec<_> = Regex::new(r"<si><t[^>]*>(.*?)</t></si>").unwrap()
+            .captures_iter(&strings)
+            .map(|c| c[1].to_string())
+            .collect();
+        Some(strings[value.parse::<usize>().unwrap()].clone())
+    }
+
+    /// The formula in a cell, as it would be typed in without the leading "="
+    pub(crate) fn formula(&mut self, sheet: usize, cell: &str) -> Option<String> {
+        let cell = self.cell(sheet, cell)?;
+        Some(unescape(&Regex::new(r"<f>(.*?)</f>").unwrap().captures(&cell)?[1]))
+    }
+
+    /// The cell ranges each chart draws on, e.g. "Charts!$B$3:$B$4", one list per chart in order
+    pub(crate) fn chart_ranges(&mut self) -> Vec<Vec<String>> {
+        let count = self.0.file_names().filter(|name| name.starts_with("xl/charts/chart")).count();
+        let range = Regex::new(r"<c:f>(.*?)</c:f>").unwrap();
+        (1..=count)
+            .map(|n| {
+                let chart = self.part(&format!("xl/charts/chart{n}.xml"));
+                range.captures_iter(&chart).map(|c| unescape(&c[1])).collect()
+            })
+            .collect()
+    }
+
+    /// The number format a cell is styled with, e.g. "[h]:mm:ss.0"
+    pub(crate) fn number_format(&mut self, sheet: usize, cell: &str) -> Option<String> {
+        let cell = self.cell(sheet, cell)?;
+        let style: usize = Regex::new(r#" s="(\d+)""#).unwrap().captures(&cell)?[1].parse().unwrap();
+        let styles = self.part("xl/styles.xml");
+        let xfs = &styles[styles.find("<cellXfs")?..];
+        let xf = Regex::new(r"<xf [^>]*").unwrap().find_iter(xfs).nth(style)?.as_str().to_string();
+        let id = &Regex::new(r#"numFmtId="(\d+)""#).unwrap().captures(&xf)?[1];
+        let format = Regex::new(&format!(r#"<numFmt numFmtId="{id}" formatCode="([^"]*)""#)).unwrap();
+        Some(unescape(&format.captures(&styles)?[1]))
+    }
+}
+
+fn unescape(xml: &str) -> String {
+    xml.replace("&quot;", "\"").replace("&apos;", "'").replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::structures::{self, UidMap};
+
+#[derive(Debug, Clone)]
+pub enum ProblemKind {
+    TimesLength { expected: usize, found: usize },
+    ColorsLength { expected: usize, found: usize },
+    SplitsLength { expected: usize, found: usize },
+    SplitCount { stage: usize, expected: usize, found: usize },
+    /// Split times on a stage we have no split distances for. We can't use them, but they don't
+    /// break anything either.
+    UnusableSplits { stage: usize, found: usize },
+    UnknownDriver { uid: usize },
+    UnknownCodriver { uid: usize },
+    DuplicateNumber,
+    Spreadsheet { message: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub slug: String,
+    pub number: Option<usize>,
+    pub kind: ProblemKind,
+}
+
+impl Problem {
+    pub fn new(rally: &structures::Rally, number: Option<usize>, kind: ProblemKind) -> Self {
+        Problem {
+            slug: rally.slug.clone(),
+            number,
+            kind,
+        }
+    }
+
+    /// Warnings are worth knowing about, but shouldn't stop a deploy
+    pub fn is_warning(&self) -> bool {
+        matches!(self.kind, ProblemKind::UnusableSplits { .. })
+    }
+}
+
+impl fmt::Display for ProblemKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProblemKind::TimesLength { expected, found } =>
+                write!(f, "has {} stage times, expected {}", found, expected),
+            ProblemKind::ColorsLength { expected, found } =>
+                write!(f, "has {} colors, expected {}", found, expected),
+            ProblemKind::SplitsLength { expected, found } =>
+                write!(f, "has splits for {} stages, expected {}", found, expected),
+            ProblemKind::SplitCount { stage, expected, found } =>
+                write!(f, "has {} splits on SS{}, expected {}", found, stage + 1, expected),
+            ProblemKind::UnusableSplits { stage, found } =>
+                write!(f, "has {} splits on SS{}, which has no split distances", found, stage + 1),
+            ProblemKind::UnknownDriver { uid } =>
+                write!(f, "driver uid {} is not in the uid map", uid),
+            ProblemKind::UnknownCodriver { uid } =>
+                write!(f, "codriver uid {} is not in the uid map", uid),
+            ProblemKind::DuplicateNumber =>
+                write!(f, "car number is used by more than one entry"),
+            ProblemKind::Spreadsheet { message } =>
+                write!(f, "spreadsheet failed to build: {}", message),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.number {
+            Some(number) => write!(f, "{} #{}: {}", self.slug, number, self.kind),
+            None => write!(f, "{}: {}", self.slug, self.kind),
+        }
+    }
+}
+
+/// Check that every entry lines up with the stages of the rally and resolves against the uid map
+pub fn validate_rally(rally: &structures::Rally, uids: &UidMap) -> Vec<Problem> {
+    let mut problems = vec![];
+    let stages = rally.stages.len();
+
+    let mut numbers: HashMap<usize, usize> = HashMap::new();
+    for entry in &rally.entries {
+        *numbers.entry(entry.number).or_default() += 1;
+    }
+    let mut duplicates: Vec<_> = numbers.iter()
+        .filter(|(_, count)| **count > 1)
+        .map(|(number, _)| *number)
+        .collect();
+    duplicates.sort();
+    for number in duplicates {
+        problems.push(Problem::new(rally, Some(number), ProblemKind::DuplicateNumber));
+    }
+
+    for entry in &rally.entries {
+        let number = Some(entry.number);
+        if entry.times.len() != stages {
+            problems.push(Problem::new(rally, number, ProblemKind::TimesLength { expected: stages, found: entry.times.len() }));
+        }
+        if entry.colors.len() != stages {
+            problems.push(Problem::new(rally, number, ProblemKind::ColorsLength { expected: stages, found: entry.colors.len() }));
+        }
+        if let Some(splits) = &entry.splits {
+            if splits.len() != stages {
+                problems.push(Problem::new(rally, number, ProblemKind::SplitsLength { expected: stages, found: splits.len() }));
+            }
+            for (i, (splits, stage)) in splits.iter().zip(rally.stages.iter()).enumerate() {
+                let expected = stage.splits.as_ref().map_or(0, |s| s.len());
+                if splits.len() == expected {
+                    continue
+                }
+                let kind = if stage.has_splits() {
+                    ProblemKind::SplitCount { stage: i, expected, found: splits.len() }
+                } else {
+                    ProblemKind::UnusableSplits { stage: i, found: splits.len() }
+                };
+                problems.push(Problem::new(rally, number, kind));
+            }
+        }
+        if !uids.contains_key(&entry.driverUID) {
+            problems.push(Problem::new(rally, number, ProblemKind::UnknownDriver { uid: entry.driverUID }));
+        }
+        if !uids.contains_key(&entry.codriverUID) {
+            problems.push(Problem::new(rally, number, ProblemKind::UnknownCodriver { uid: entry.codriverUID }));
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{entry, rally, stage, uids};
+    use serde_json::json;
+
+    fn kinds(rally: &structures::Rally) -> Vec<(Option<usize>, String)> {
+        validate_rally(rally, &uids(9)).iter()
+            .map(|p| (p.number, format!("{:?}", p.kind)))
+            .collect()
+    }
+
+    #[test]
+    fn clean_rally() {
+        let rally = rally("clean", vec![stage("One", 1.0)], vec![
+            entry(10, 0, "O4WD", "National", &["01:00.0"]),
+            entry(20, 2, "O4WD", "National", &["01:05.0"]),
+        ]);
+        assert!(validate_rally(&rally, &uids(9)).is_empty());
+    }
+
+    #[test]
+    fn length_mismatch() {
+        let mut short = entry(10, 0, "O4WD", "National", &["01:00.0"]);
+        short["colors"] = json!(["", ""]);
+        let times = rally("times", vec![stage("One", 1.0), stage("Two", 1.0)], vec![short]);
+        assert_eq!(kinds(&times), vec![
+            (Some(10), "TimesLength { expected: 2, found: 1 }".to_string()),
+        ]);
+
+        let mut colors = entry(10, 0, "O4WD", "National", &["01:00.0"]);
+        colors["colors"] = json!([]);
+        let mut splits = entry(20, 2, "O4WD", "National", &["01:05.0"]);
+        splits["splits"] = json!([[], []]);
+        let others = rally("others", vec![stage("One", 1.0)], vec![colors, splits]);
+        assert_eq!(kinds(&others), vec![
+            (Some(10), "ColorsLength { expected: 1, found: 0 }".to_string()),
+            (Some(20), "SplitsLength { expected: 1, found: 2 }".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn split_count_mismatch() {
+        let mut split = stage("Split", 2.0);
+        split["splits"] = json!([1.0]);
+        let mut two = entry(10, 0, "O4WD", "National", &["02:00.0", "01:00.0"]);
+        two["splits"] = json!([["00:30.0", "01:00.0"], []]);
+        let mut unusable = entry(20, 2, "O4WD", "National", &["02:05.0", "01:05.0"]);
+        unusable["splits"] = json!([["01:02.0"], ["00:30.0"]]);
+        let rally = rally("splits", vec![split, stage("Plain", 1.0)], vec![two, unusable]);
+        let problems = validate_rally(&rally, &uids(9));
+        assert_eq!(kinds(&rally), vec![
+            (Some(10), "SplitCount { stage: 0, expected: 1, found: 2 }".to_string()),
+            (Some(20), "UnusableSplits { stage: 1, found: 1 }".to_string()),
+        ]);
+        assert!(!problems[0].is_warning());
+        assert!(problems[1].is_warning());
+    }
+
+    #[test]
+    fn unknown_uid() {
+        let rally = rally("uids", vec![stage("One", 1.0)], vec![
+            entry(10, 9, "O4WD", "National", &["01:00.0"]),
+            entry(20, 20, "O4WD", "National", &["01:05.0"]),
+        ]);
+        assert_eq!(kinds(&rally), vec![
+            (Some(10), "UnknownCodriver { uid: 10 }".to_string()),
+            (Some(20), "UnknownDriver { uid: 20 }".to_string()),
+            (Some(20), "UnknownCodriver { uid: 21 }".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn duplicate_number() {
+        let rally = rally("duplicates", vec![stage("One", 1.0)], vec![
+            entry(10, 0, "O4WD", "National", &["01:00.0"]),
+            entry(10, 2, "O4WD", "National", &["01:05.0"]),
+        ]);
+        let problems = validate_rally(&rally, &uids(9));
+        assert_eq!(kinds(&rally), vec![(Some(10), "DuplicateNumber".to_string())]);
+        assert_eq!(problems[0].to_string(), "duplicates #10: car number is used by more than one entry");
+    }
+}